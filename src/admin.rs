@@ -0,0 +1,223 @@
+use std::{
+  collections::VecDeque,
+  sync::{Arc, LazyLock, Mutex},
+  time::SystemTime,
+};
+
+use axum::{
+  extract::Path,
+  headers::{
+    authorization::{Basic, Bearer},
+    Authorization, ContentType, HeaderMapExt as _,
+  },
+  http::{HeaderMap, Method, Request, StatusCode},
+  middleware::{self, Next},
+  response::{IntoResponse, Response},
+  routing::{get, post},
+  Extension, Json, Router, TypedHeader,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{
+  audio_store::{AudioStoreRef, FileInfo},
+  feed,
+  piped::{PipedInstance, PipedInstanceRepo, PipedInstanceStat},
+  util::{constant_time_eq, YTDLP_JOBS},
+  Error, Result,
+};
+
+// the admin pages are only served when set. log in with any user name
+// and this as the password, or send it as a bearer token.
+static ADMIN_TOKEN: LazyLock<Option<String>> =
+  LazyLock::new(|| std::env::var("ADMIN_TOKEN").ok().filter(|s| !s.is_empty()));
+
+const ADMIN_HTML: &str = include_str!("../html/admin.html");
+
+// set by the admin page on its posts. cross-site forms can't set it, and
+// cross-origin scripts can't without a preflight we don't answer.
+const ADMIN_REQUEST_HEADER: &str = "x-admin-request";
+
+const MAX_RECENT_ERRORS: usize = 50;
+
+static RECENT_ERRORS: LazyLock<Mutex<VecDeque<RecentError>>> =
+  LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, Serialize)]
+struct RecentError {
+  // unix time
+  at: u64,
+  status: u16,
+  message: String,
+}
+
+pub fn record_error(status: StatusCode, error: &Error) {
+  // bad requests are the clients' business
+  if status.is_client_error() {
+    return;
+  }
+
+  let at = SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  let error = RecentError {
+    at,
+    status: status.as_u16(),
+    message: error.to_string(),
+  };
+
+  let mut errors = RECENT_ERRORS.lock().unwrap();
+  if errors.len() == MAX_RECENT_ERRORS {
+    errors.pop_front();
+  }
+  errors.push_back(error);
+}
+
+pub fn router() -> Router {
+  Router::new()
+    .route("/", get(admin_page))
+    .route("/api/status", get(status))
+    .route("/api/files/:key/evict", post(evict_file))
+    .route("/api/channels/:channel_id/refresh", post(refresh_channel))
+    .route("/api/piped/refresh", post(refresh_piped))
+    .route_layer(middleware::from_fn(authenticate))
+}
+
+async fn authenticate<B>(req: Request<B>, next: Next<B>) -> Result<Response> {
+  let Some(token) = ADMIN_TOKEN.as_deref() else {
+    return Err(Error::AdminDisabled);
+  };
+
+  if !is_authorized(req.method(), req.headers(), token) {
+    return Err(Error::Unauthorized);
+  }
+
+  Ok(next.run(req).await)
+}
+
+fn is_authorized(method: &Method, headers: &HeaderMap, token: &str) -> bool {
+  let given = if let Some(auth) = headers.typed_get::<Authorization<Basic>>() {
+    // browsers send the basic credentials along with cross-site posts
+    if method != Method::GET && !headers.contains_key(ADMIN_REQUEST_HEADER) {
+      return false;
+    }
+    auth.password().to_string()
+  } else if let Some(auth) = headers.typed_get::<Authorization<Bearer>>() {
+    auth.token().to_string()
+  } else {
+    return false;
+  };
+
+  constant_time_eq(given.as_bytes(), token.as_bytes())
+}
+
+async fn admin_page() -> impl IntoResponse {
+  (TypedHeader::<ContentType>(ContentType::html()), ADMIN_HTML)
+}
+
+#[derive(Serialize)]
+struct Status {
+  files: Vec<FileInfo>,
+  ytdlp_jobs: Vec<JobInfo>,
+  piped_instance: PipedInstance,
+  piped_candidates: Vec<PipedInstanceStat>,
+  recent_errors: Vec<RecentError>,
+}
+
+#[derive(Serialize)]
+struct JobInfo {
+  description: String,
+  queued_secs: u64,
+  // none while waiting for a slot
+  running_secs: Option<u64>,
+}
+
+async fn status(
+  Extension(audio_store): Extension<Arc<AudioStoreRef>>,
+) -> Result<Json<Status>> {
+  let ytdlp_jobs = YTDLP_JOBS
+    .lock()
+    .unwrap()
+    .values()
+    .map(|job| JobInfo {
+      description: job.description.clone(),
+      queued_secs: job.queued_at.elapsed().as_secs(),
+      running_secs: job.started_at.map(|t| t.elapsed().as_secs()),
+    })
+    .collect();
+
+  // newest first
+  let recent_errors = RECENT_ERRORS
+    .lock()
+    .unwrap()
+    .iter()
+    .rev()
+    .cloned()
+    .collect();
+
+  Ok(Json(Status {
+    files: audio_store.list_files().await?,
+    ytdlp_jobs,
+    piped_instance: PipedInstanceRepo::instance(),
+    piped_candidates: PipedInstanceRepo::candidates(),
+    recent_errors,
+  }))
+}
+
+async fn evict_file(
+  Path(key): Path<String>,
+  Extension(audio_store): Extension<Arc<AudioStoreRef>>,
+) -> Result<Json<Value>> {
+  let evicted = audio_store.evict(&key).await?;
+  Ok(Json(json!({ "evicted": evicted })))
+}
+
+async fn refresh_channel(
+  Path(channel_id): Path<String>,
+) -> Result<Json<Value>> {
+  let podcast = feed::refresh_channel(&channel_id).await?;
+  Ok(Json(json!({
+    "title": podcast.title,
+    "episodes": podcast.episodes.len(),
+  })))
+}
+
+async fn refresh_piped() -> Json<Value> {
+  PipedInstanceRepo::request_update();
+  Json(json!({ "requested": true }))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_is_authorized() {
+    let check_with = |method: Method, auth: Option<&str>, header: bool| {
+      let mut headers = HeaderMap::new();
+      if let Some(auth) = auth {
+        headers.insert("authorization", auth.parse().unwrap());
+      }
+      if header {
+        headers.insert(ADMIN_REQUEST_HEADER, "1".parse().unwrap());
+      }
+      is_authorized(&method, &headers, "secret")
+    };
+    let check = |auth| check_with(Method::GET, auth, false);
+
+    assert!(!check(None));
+    assert!(check(Some("Bearer secret")));
+    assert!(!check(Some("Bearer secre")));
+    // admin:secret
+    assert!(check(Some("Basic YWRtaW46c2VjcmV0")));
+    // admin:wrong
+    assert!(!check(Some("Basic YWRtaW46d3Jvbmc=")));
+
+    // posts with basic auth only come from the admin page
+    let basic = Some("Basic YWRtaW46c2VjcmV0");
+    assert!(!check_with(Method::POST, basic, false));
+    assert!(check_with(Method::POST, basic, true));
+    assert!(check_with(Method::POST, Some("Bearer secret"), false));
+  }
+}
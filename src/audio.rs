@@ -5,7 +5,10 @@ use std::sync::Arc;
 use axum::body::StreamBody;
 use axum::Extension;
 use axum::{
-  body, extract::Path, headers::HeaderMap, http::Response,
+  body,
+  extract::{Path, Query},
+  headers::HeaderMap,
+  http::Response,
   response::IntoResponse,
 };
 use futures::stream::BoxStream;
@@ -18,16 +21,28 @@ use tokio::fs::File;
 use tokio::io::AsyncSeekExt as _;
 use tokio_util::io::ReaderStream;
 
-use crate::audio_store::AudioStoreRef;
-use crate::extractor::{self, Extraction, Extractor};
+use crate::audio_options::AudioOptions;
+use crate::audio_signature::SignedAudio;
+use crate::audio_store::{AudioFile, AudioStoreRef};
+use crate::channel_filter;
+use crate::config::CONFIG;
+use crate::extractor::{self, Extraction};
+use crate::health::{BackendId, HEALTH};
+use crate::invidious::InvidiousInstance;
 use crate::piped::PipedInstance;
-use crate::util::{race_ordered_first_ok, ByteStream};
+use crate::rate_limit::DOWNLOAD_RATE_LIMIT;
+use crate::subscriber::AudioAccess;
+use crate::util::{race_ordered_first_ok, with_timeout, ByteStream};
 use crate::{Error, Result};
 
 #[axum::debug_handler]
 pub async fn get_audio(
   Path(video_id): Path<String>,
+  Query(audio_options): Query<AudioOptions>,
   piped: PipedInstance,
+  invidious: InvidiousInstance,
+  // both must pass before anything is downloaded
+  (_, access): (SignedAudio, AudioAccess),
   req_headers: HeaderMap,
   Extension(audio_store): Extension<Arc<AudioStoreRef>>,
 ) -> Result<impl IntoResponse> {
@@ -41,22 +56,48 @@ pub async fn get_audio(
     .unwrap_or("none");
 
   eprintln!(
-    "client requesting audio: {} (range: {}, user-agent: {})",
-    video_id, range, user_agent
+    "client requesting audio: {} (options: {:?}, range: {}, user-agent: {})",
+    video_id, audio_options, range, user_agent
   );
 
-  #[allow(unused)]
-  let piped_extractor = extractor::Piped(&piped);
-  #[allow(unused)]
-  let ytdlp_stream = extractor::YtdlpStream;
-  #[allow(unused)]
-  let ytdlp_file = extractor::YtdlpFile::new(audio_store.clone());
-  let extractions: Vec<_> = vec![
-    // ytdlp_stream.extract(&video_id),
-    ytdlp_file.extract(&video_id),
-    // extractor::Rustube.extract(&video_id),
-    // piped_extractor.extract(&video_id),
-  ];
+  // audio already downloaded or being downloaded is free, and so are the
+  // further range requests of a client that was charged for the episode
+  let audio_id = audio_options.audio_id(&video_id);
+  let format = audio_options.audio_format;
+  if !audio_store.contains(&audio_id, format).await? {
+    let item = format!("{}.{}", audio_id, format.extension());
+    DOWNLOAD_RATE_LIMIT.check_once(access.client(), &item)?;
+  }
+
+  // after the rate limit, as videos not seen in a feed are looked up
+  channel_filter::check_video(&video_id).await?;
+
+  let extractors: Vec<_> = if audio_options.is_original() {
+    let configs = CONFIG.extractors.iter().collect();
+    HEALTH
+      .select(configs, |c| BackendId::extractor(c.backend.name()))
+      .into_iter()
+      .map(|config| {
+        let extractor =
+          extractor::build(&config.backend, &audio_store, &piped, &invidious);
+        (extractor, config.backend.name(), config.timeout())
+      })
+      .collect()
+  } else {
+    // transcoding needs the file downloaded by yt-dlp
+    let transcode =
+      extractor::Transcode::new(audio_store.clone(), audio_options);
+    vec![(Box::new(transcode) as _, "transcode", None)]
+  };
+
+  let extractions = extractors
+    .iter()
+    .map(|(extractor, name, timeout)| {
+      let extraction = extractor.extract(&video_id);
+      let extraction = with_timeout(*timeout, name, extraction);
+      HEALTH.track(BackendId::extractor(name), extraction)
+    })
+    .collect();
 
   let extraction = race_ordered_first_ok(extractions).await?;
 
@@ -76,9 +117,10 @@ pub async fn get_audio(
       .await
       .map(|x| x.into_response()),
     Extraction::File { file, mime_type } => {
-      serve_file(file, mime_type, req_headers)
-        .await
-        .map(|x| x.into_response())
+      serve_file(file, mime_type, req_headers).await
+    }
+    Extraction::GrowingFile { file, mime_type } => {
+      serve_growing_file(file, mime_type, req_headers).await
     }
   }
 }
@@ -155,7 +197,7 @@ async fn serve_file(
   mut file: File,
   mime_type: String,
   req_headers: HeaderMap,
-) -> Result<impl IntoResponse> {
+) -> Result<axum::response::Response> {
   let range = req_headers
     .get(header::RANGE)
     .and_then(|range| range.to_str().ok());
@@ -164,6 +206,10 @@ async fn serve_file(
   let metadata = file.metadata().await.expect("metadata");
   let len = metadata.len() as usize;
 
+  if from.is_some_and(|from| from >= len) {
+    return range_not_satisfiable(len);
+  }
+
   let from = from.unwrap_or(0).min(len as usize);
   let to = to.unwrap_or(len - 1).min(len - 1).max(from);
   let content_len = to - from + 1;
@@ -190,7 +236,62 @@ async fn serve_file(
     http::StatusCode::OK
   };
 
-  Ok((status, headers, StreamBody::new(stream)))
+  Ok((status, headers, StreamBody::new(stream)).into_response())
+}
+
+fn range_not_satisfiable(len: usize) -> Result<axum::response::Response> {
+  let status = http::StatusCode::RANGE_NOT_SATISFIABLE;
+  let content_range = HeaderValue::from_str(&format!("bytes */{len}"))?;
+  Ok((status, [(header::CONTENT_RANGE, content_range)]).into_response())
+}
+
+// serve a file while it's being downloaded. its length isn't known yet,
+// so only ranges with both ends are served before the download finishes.
+async fn serve_growing_file(
+  file: Arc<AudioFile>,
+  mime_type: String,
+  req_headers: HeaderMap,
+) -> Result<axum::response::Response> {
+  let range = req_headers
+    .get(header::RANGE)
+    .and_then(|range| range.to_str().ok());
+
+  let mut headers = HeaderMap::new();
+  headers.insert(header::CONTENT_TYPE, HeaderValue::from_str(&mime_type)?);
+  headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+  match parse_range(range) {
+    (None | Some(0), None) => {
+      let stream = file.growing_stream(0, None).await?;
+      let resp = (http::StatusCode::OK, headers, StreamBody::new(stream));
+      Ok(resp.into_response())
+    }
+    (Some(from), Some(to)) if from <= to => {
+      // the content length can only be promised for bytes that exist,
+      // the final length is known once the download is done
+      file.wait_for_len(to as u64 + 1).await?;
+      if file.is_ready() {
+        return serve_file(file.open().await?, mime_type, req_headers).await;
+      }
+
+      let content_len = (to - from + 1) as u64;
+      let stream = file.growing_stream(from as u64, Some(content_len)).await?;
+      headers.insert(
+        header::CONTENT_LENGTH,
+        HeaderValue::from_str(&content_len.to_string())?,
+      );
+      headers.insert(
+        header::CONTENT_RANGE,
+        HeaderValue::from_str(&format!("bytes {}-{}/*", from, to))?,
+      );
+      let status = http::StatusCode::PARTIAL_CONTENT;
+      Ok((status, headers, StreamBody::new(stream)).into_response())
+    }
+    _ => {
+      file.wait_until_finished().await?;
+      serve_file(file.open().await?, mime_type, req_headers).await
+    }
+  }
 }
 
 async fn proxy_play_link(
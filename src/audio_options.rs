@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+use crate::sponsorblock::Categories;
+
+// the audio format served to the podcast apps. yt-dlp downloads m4a,
+// the other formats are transcoded from it with ffmpeg.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+  #[default]
+  M4a,
+  Mp3,
+  Opus,
+}
+
+impl AudioFormat {
+  pub fn extension(self) -> &'static str {
+    match self {
+      AudioFormat::M4a => "m4a",
+      AudioFormat::Mp3 => "mp3",
+      AudioFormat::Opus => "opus",
+    }
+  }
+
+  pub fn from_extension(ext: &str) -> Option<Self> {
+    match ext {
+      "m4a" => Some(AudioFormat::M4a),
+      "mp3" => Some(AudioFormat::Mp3),
+      "opus" => Some(AudioFormat::Opus),
+      _ => None,
+    }
+  }
+
+  pub fn mime_type(self) -> &'static str {
+    match self {
+      AudioFormat::M4a => "audio/mp4",
+      AudioFormat::Mp3 => "audio/mpeg",
+      AudioFormat::Opus => "audio/ogg",
+    }
+  }
+
+  // ffmpeg output options to encode into this format
+  pub fn ffmpeg_args(self) -> &'static [&'static str] {
+    match self {
+      AudioFormat::M4a => &["-c:a", "aac", "-b:a", "128k", "-f", "ipod"],
+      AudioFormat::Mp3 => &["-c:a", "libmp3lame", "-q:a", "4", "-f", "mp3"],
+      AudioFormat::Opus => &["-c:a", "libopus", "-b:a", "64k", "-f", "opus"],
+    }
+  }
+}
+
+// how the audio gets processed before being served, taken from the
+// query string of both the feed and the audio urls.
+// e.g. ?audio_format=mp3&sponsorblock=sponsor,selfpromo
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct AudioOptions {
+  #[serde(default)]
+  pub audio_format: AudioFormat,
+  // sponsorblock categories to cut out of the audio
+  pub sponsorblock: Option<Categories>,
+}
+
+impl AudioOptions {
+  // the audio downloaded by yt-dlp can be served as is
+  pub fn is_original(&self) -> bool {
+    self.audio_format == AudioFormat::default() && self.sponsorblock.is_none()
+  }
+
+  // the id the audio is stored under in the audio store
+  pub fn audio_id(&self, video_id: &str) -> String {
+    match &self.sponsorblock {
+      Some(categories) => format!("{}.{}", video_id, categories.tag()),
+      None => video_id.to_string(),
+    }
+  }
+
+  pub fn query_pairs(&self) -> Vec<(&'static str, String)> {
+    let mut pairs = vec![];
+    if self.audio_format != AudioFormat::default() {
+      pairs.push(("audio_format", self.audio_format.extension().to_string()));
+    }
+    if let Some(categories) = &self.sponsorblock {
+      pairs.push(("sponsorblock", categories.to_string()));
+    }
+    pairs
+  }
+}
@@ -0,0 +1,111 @@
+use std::sync::LazyLock;
+
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Path, Query};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+  util::{hmac_sign, hmac_verify},
+  Error, Result,
+};
+
+// audio urls in the feeds are signed with this key when set, and
+// /audio/:video_id only serves the videos it signed.
+static AUDIO_SIGNING_KEY: LazyLock<Option<String>> = LazyLock::new(|| {
+  std::env::var("AUDIO_SIGNING_KEY")
+    .ok()
+    .filter(|s| !s.is_empty())
+});
+
+// set to false while migrating, so the unsigned urls in feeds fetched
+// before the key was set keep working. bad signatures are still refused.
+static AUDIO_SIGNATURE_REQUIRED: LazyLock<bool> = LazyLock::new(|| {
+  std::env::var("AUDIO_SIGNATURE_REQUIRED")
+    .map(|s| s != "false" && s != "0")
+    .unwrap_or(true)
+});
+
+// the signature to put on the audio url of the video, if signing is on
+pub fn sign(video_id: &str) -> Option<(&'static str, String)> {
+  let key = AUDIO_SIGNING_KEY.as_ref()?;
+  Some(("sig", hmac_sign(key.as_bytes(), video_id)))
+}
+
+// rejects requests for audio, chapters or transcripts of videos we
+// didn't sign
+pub struct SignedAudio;
+
+#[derive(Deserialize)]
+struct SignatureQuery {
+  sig: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SignedAudio
+where
+  S: Send + Sync,
+{
+  type Rejection = Error;
+
+  async fn from_request_parts(
+    parts: &mut http::request::Parts,
+    state: &S,
+  ) -> Result<Self> {
+    let Some(key) = AUDIO_SIGNING_KEY.as_ref() else {
+      return Ok(SignedAudio);
+    };
+
+    let Path(video_id) = Path::<String>::from_request_parts(parts, state)
+      .await
+      .map_err(|_| Error::InvalidSignature)?;
+    let query = Query::<SignatureQuery>::from_request_parts(parts, state)
+      .await
+      .map_err(|_| Error::InvalidSignature)?;
+
+    verify(
+      key,
+      &video_id,
+      query.0.sig.as_deref(),
+      *AUDIO_SIGNATURE_REQUIRED,
+    )?;
+    Ok(SignedAudio)
+  }
+}
+
+fn verify(
+  key: &str,
+  video_id: &str,
+  signature: Option<&str>,
+  required: bool,
+) -> Result<()> {
+  match signature {
+    Some(signature) if hmac_verify(key.as_bytes(), video_id, signature) => {
+      Ok(())
+    }
+    Some(_) => Err(Error::InvalidSignature),
+    None if required => Err(Error::InvalidSignature),
+    None => {
+      warn!("serving unsigned audio url for {video_id}");
+      Ok(())
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_verify() {
+    let signature = hmac_sign(b"key", "abc");
+
+    assert!(verify("key", "abc", Some(&signature), true).is_ok());
+    assert!(verify("key", "abd", Some(&signature), true).is_err());
+    assert!(verify("other", "abc", Some(&signature), true).is_err());
+    assert!(verify("key", "abc", None, true).is_err());
+    // while migrating
+    assert!(verify("key", "abc", None, false).is_ok());
+    assert!(verify("key", "abd", Some(&signature), false).is_err());
+  }
+}
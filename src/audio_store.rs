@@ -1,40 +1,107 @@
 use std::{
+  collections::HashMap,
   future::Future,
+  io::{ErrorKind, SeekFrom},
   path::{Path, PathBuf},
-  sync::Arc,
-  time::Instant,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, LazyLock,
+  },
+  time::{Duration, SystemTime},
 };
 
-use kameo::{actor::ActorRef, messages, Actor};
-use lru_time_cache::LruCache;
-use tokio::{fs::File, sync::Mutex};
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt as _};
+use kameo::{actor::ActorRef, error::SendError, messages, Actor};
+use serde::Serialize;
+use tokio::{
+  fs::File,
+  io::{AsyncReadExt as _, AsyncSeekExt as _},
+  sync::watch,
+};
 use tracing::{info, warn};
 
-use crate::{Error, Result};
+use crate::{
+  audio_options::AudioFormat, extractor::YtdlpError, metrics::METRICS, Error,
+  Result,
+};
+
+// the disk space the audio files may take, e.g. "500M" or "2G"
+static AUDIO_STORE_QUOTA: LazyLock<u64> = LazyLock::new(|| {
+  std::env::var("AUDIO_STORE_QUOTA")
+    .ok()
+    .map(|s| parse_size(&s).expect("Invalid AUDIO_STORE_QUOTA"))
+    .unwrap_or(2 << 30)
+});
+
+// seconds a file may go unrequested before it gets deleted
+static AUDIO_STORE_MAX_IDLE: LazyLock<Duration> = LazyLock::new(|| {
+  let secs = std::env::var("AUDIO_STORE_MAX_IDLE")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(7 * 24 * 60 * 60);
+  Duration::from_secs(secs)
+});
+
+fn parse_size(s: &str) -> Option<u64> {
+  let s = s.trim();
+  let (num, unit) = match s.char_indices().last()? {
+    (i, 'K' | 'k') => (&s[..i], 1 << 10),
+    (i, 'M' | 'm') => (&s[..i], 1 << 20),
+    (i, 'G' | 'g') => (&s[..i], 1 << 30),
+    _ => (s, 1),
+  };
+  num.trim().parse::<u64>().ok().map(|n| n * unit)
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioFileState {
   New,
+  Downloading,
   Ready,
+  // with the reason if yt-dlp told us
+  Failed(Option<YtdlpError>),
+  // the download was dropped before it finished, the next one asking
+  // starts it over
+  Cancelled,
+}
+
+impl AudioFileState {
+  pub fn name(&self) -> &'static str {
+    match self {
+      AudioFileState::New => "new",
+      AudioFileState::Downloading => "downloading",
+      AudioFileState::Ready => "ready",
+      AudioFileState::Failed(_) => "failed",
+      AudioFileState::Cancelled => "cancelled",
+    }
+  }
 }
 
 pub struct AudioFile {
   pub id: String,
+  pub format: AudioFormat,
   pub path: PathBuf,
   pub temp_path: PathBuf,
-  pub state: Mutex<AudioFileState>,
-  #[allow(dead_code)]
-  pub created_at: Instant,
+  pub state: watch::Sender<AudioFileState>,
+  pub created_at: SystemTime,
+  // files are kept on disk across restarts unless evicted
+  evicted: AtomicBool,
 }
 
 impl Drop for AudioFile {
   fn drop(&mut self) {
     // delete the file on drop
-    if !self.path.exists() {
+    if !self.evicted.load(Ordering::Relaxed) {
       return;
     }
 
     std::fs::remove_file(&self.temp_path).ok();
 
+    if !self.path.exists() {
+      return;
+    }
+
     if let Err(e) = std::fs::remove_file(&self.path) {
       eprintln!("failed to delete file: {}", e);
     } else {
@@ -43,86 +110,365 @@ impl Drop for AudioFile {
   }
 }
 
+struct StoredFile {
+  file: Arc<AudioFile>,
+  last_access: SystemTime,
+}
+
+impl StoredFile {
+  // the bytes on disk, including a download in progress
+  fn size(&self) -> u64 {
+    [&self.file.path, &self.file.temp_path]
+      .iter()
+      .filter_map(|p| std::fs::metadata(p).ok())
+      .map(|m| m.len())
+      .sum()
+  }
+
+  // only files nobody is downloading or serving can go
+  fn is_evictable(&self) -> bool {
+    Arc::strong_count(&self.file) == 1
+  }
+}
+
 #[derive(Actor)]
 pub struct AudioStore {
   base_dir: PathBuf,
-  files: LruCache<String, Arc<AudioFile>>,
+  quota: u64,
+  max_idle: Duration,
+  files: HashMap<String, StoredFile>,
 }
 
 pub struct AudioStoreRef(ActorRef<AudioStore>);
 
+// a stored file as shown on the admin page
+#[derive(Debug, Serialize)]
+pub struct FileInfo {
+  pub key: String,
+  pub id: String,
+  pub state: &'static str,
+  pub size: u64,
+  pub age_secs: u64,
+  pub idle_secs: u64,
+}
+
 #[messages]
 impl AudioStore {
   #[message]
   async fn get_or_allocate(
     &mut self,
     audio_id: String,
+    format: AudioFormat,
   ) -> Result<Arc<AudioFile>> {
-    if let Some(file) = self.files.get(&audio_id) {
-      return Ok(file.clone());
+    let key = file_key(&audio_id, format);
+    if let Some(stored) = self.files.get_mut(&key) {
+      METRICS.inc("audio_store_requests_total", &[("result", "hit")]);
+      stored.last_access = SystemTime::now();
+      return Ok(stored.file.clone());
     }
+    METRICS.inc("audio_store_requests_total", &[("result", "miss")]);
+
+    // make room for the new file
+    self.evict()?;
 
-    let file = AudioFile::new(&self.base_dir, &audio_id);
+    let file = AudioFile::new(&self.base_dir, &audio_id, format);
     let value = Arc::new(file);
-    self.files.insert(audio_id, value.clone());
+    self.insert(key, value.clone(), SystemTime::now());
     Ok(value)
   }
 
   #[message]
-  async fn remove(&mut self, audio_id: String) {
-    self.files.remove(&audio_id);
+  async fn remove(&mut self, audio_id: String, format: AudioFormat) {
+    self.evict_file(&file_key(&audio_id, format));
+  }
+
+  #[message]
+  async fn list_files(&mut self) -> Vec<FileInfo> {
+    let now = SystemTime::now();
+    let since = |t: SystemTime| now.duration_since(t).unwrap_or_default();
+
+    let mut files: Vec<_> = self
+      .files
+      .iter()
+      .map(|(key, stored)| FileInfo {
+        key: key.clone(),
+        id: stored.file.id.clone(),
+        state: stored.file.state.borrow().name(),
+        size: stored.size(),
+        age_secs: since(stored.file.created_at).as_secs(),
+        idle_secs: since(stored.last_access).as_secs(),
+      })
+      .collect();
+    files.sort_by_key(|f| f.idle_secs);
+    files
+  }
+
+  #[message]
+  async fn contains(&mut self, audio_id: String, format: AudioFormat) -> bool {
+    self.files.contains_key(&file_key(&audio_id, format))
+  }
+
+  // whether there was such a file
+  #[message]
+  async fn evict_key(&mut self, key: String) -> bool {
+    let found = self.files.contains_key(&key);
+    self.evict_file(&key);
+    found
   }
 }
 
 impl AudioStore {
   pub fn new(base_dir: impl AsRef<Path>) -> Self {
-    let files = LruCache::with_expiry_duration_and_capacity(
-      // expire after 10 minutes
-      std::time::Duration::from_secs(10 * 60),
-      // store up to 30 files
-      30,
-    );
+    Self::with_quota(base_dir, *AUDIO_STORE_QUOTA, *AUDIO_STORE_MAX_IDLE)
+  }
 
-    // delete all existing files
-    std::fs::remove_dir_all(&base_dir).ok();
+  fn with_quota(
+    base_dir: impl AsRef<Path>,
+    quota: u64,
+    max_idle: Duration,
+  ) -> Self {
     std::fs::create_dir_all(&base_dir).unwrap();
 
-    Self {
+    let mut store = Self {
       base_dir: base_dir.as_ref().to_owned(),
-      files,
-    }
+      quota,
+      max_idle,
+      files: HashMap::new(),
+    };
+    store.reindex();
+    store
   }
 
   pub fn spawn(self) -> AudioStoreRef {
     AudioStoreRef(kameo::spawn(self))
   }
+
+  // pick up the files downloaded before a restart and delete the
+  // partial ones
+  fn reindex(&mut self) {
+    remove_partial_files(&self.base_dir);
+    let Ok(entries) = std::fs::read_dir(&self.base_dir) else {
+      return;
+    };
+
+    for entry in entries.flatten() {
+      let path = entry.path();
+      let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        continue;
+      };
+
+      let Some((audio_id, format)) = name
+        .rsplit_once('.')
+        .and_then(|(id, ext)| Some((id, AudioFormat::from_extension(ext)?)))
+      else {
+        continue;
+      };
+
+      let modified = entry
+        .metadata()
+        .and_then(|m| m.modified())
+        .unwrap_or_else(|_| SystemTime::now());
+      let mut file = AudioFile::new(&self.base_dir, audio_id, format);
+      file.state = watch::channel(AudioFileState::Ready).0;
+      file.created_at = modified;
+
+      let key = file_key(audio_id, format);
+      self.insert(key, Arc::new(file), modified);
+    }
+
+    let usage = self.disk_usage();
+    info!(
+      "audio store indexed {} files ({} bytes)",
+      self.files.len(),
+      usage
+    );
+    self.record_usage(usage);
+  }
+
+  fn record_usage(&self, usage: u64) {
+    METRICS.set("audio_store_bytes", &[], usage as f64);
+    METRICS.set("audio_store_files", &[], self.files.len() as f64);
+  }
+
+  fn insert(&mut self, key: String, file: Arc<AudioFile>, at: SystemTime) {
+    let stored = StoredFile {
+      file,
+      last_access: at,
+    };
+    self.files.insert(key, stored);
+  }
+
+  fn disk_usage(&self) -> u64 {
+    self.files.values().map(StoredFile::size).sum()
+  }
+
+  // drop idle files, then the least recently used ones until the store
+  // is below its quota
+  fn evict(&mut self) -> Result<()> {
+    let now = SystemTime::now();
+    let idle: Vec<_> = self
+      .files
+      .iter()
+      .filter(|(_, f)| f.is_evictable())
+      .filter(|(_, f)| {
+        now.duration_since(f.last_access).unwrap_or_default() > self.max_idle
+      })
+      .map(|(k, _)| k.clone())
+      .collect();
+    for key in idle {
+      METRICS.inc("audio_store_evictions_total", &[("reason", "idle")]);
+      self.evict_file(&key);
+    }
+
+    let mut usage = self.disk_usage();
+    let mut candidates: Vec<_> = self
+      .files
+      .iter()
+      .filter(|(_, f)| f.is_evictable())
+      .map(|(k, f)| (f.last_access, f.size(), k.clone()))
+      .collect();
+    candidates.sort();
+
+    for (_, size, key) in candidates {
+      if usage < self.quota {
+        break;
+      }
+      METRICS.inc("audio_store_evictions_total", &[("reason", "quota")]);
+      self.evict_file(&key);
+      usage -= size;
+    }
+    self.record_usage(usage);
+
+    if usage >= self.quota {
+      return Err(Error::StorageFull(format!(
+        "{} of {} bytes in use",
+        usage, self.quota
+      )));
+    }
+
+    Ok(())
+  }
+
+  fn evict_file(&mut self, key: &str) {
+    if let Some(stored) = self.files.remove(key) {
+      // the file gets deleted once the last reader is done with it
+      stored.file.evicted.store(true, Ordering::Relaxed);
+    }
+  }
 }
 
 impl AudioStoreRef {
   pub async fn get_or_allocate(
     &self,
     audio_id: String,
+    format: AudioFormat,
   ) -> Result<Arc<AudioFile>> {
-    Ok(self.0.ask(GetOrAllocate { audio_id }).send().await.unwrap())
+    let msg = GetOrAllocate { audio_id, format };
+    match self.0.ask(msg).send().await {
+      Ok(file) => Ok(file),
+      Err(SendError::HandlerError(e)) => Err(e),
+      Err(e) => Err(store_unavailable(e)),
+    }
   }
 
-  pub async fn remove(&self, audio_id: &str) -> Result<()> {
+  pub async fn remove(
+    &self,
+    audio_id: &str,
+    format: AudioFormat,
+  ) -> Result<()> {
     let audio_id = audio_id.to_string();
-    self.0.ask(Remove { audio_id }).send().await.unwrap();
-    Ok(())
+    let msg = Remove { audio_id, format };
+    self.0.ask(msg).send().await.map_err(store_unavailable)
+  }
+
+  // whether the file is stored or being downloaded
+  pub async fn contains(
+    &self,
+    audio_id: &str,
+    format: AudioFormat,
+  ) -> Result<bool> {
+    let audio_id = audio_id.to_string();
+    let msg = Contains { audio_id, format };
+    self.0.ask(msg).send().await.map_err(store_unavailable)
   }
+
+  pub async fn list_files(&self) -> Result<Vec<FileInfo>> {
+    self
+      .0
+      .ask(ListFiles {})
+      .send()
+      .await
+      .map_err(store_unavailable)
+  }
+
+  pub async fn evict(&self, key: &str) -> Result<bool> {
+    let key = key.to_string();
+    let msg = EvictKey { key };
+    self.0.ask(msg).send().await.map_err(store_unavailable)
+  }
+}
+
+// the actor is gone, e.g. it panicked
+fn store_unavailable(e: impl std::fmt::Display) -> Error {
+  Error::AudioStoreUnavailable(e.to_string())
+}
+
+// delete the files of unfinished downloads
+pub fn remove_partial_files(base_dir: impl AsRef<Path>) {
+  let Ok(entries) = std::fs::read_dir(base_dir) else {
+    return;
+  };
+
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let is_partial = path
+      .file_name()
+      .and_then(|n| n.to_str())
+      .is_some_and(|n| n.contains(".temp."));
+    if is_partial {
+      info!("deleting partial audio file: {}", path.display());
+      std::fs::remove_file(&path).ok();
+    }
+  }
+}
+
+// marks the download cancelled if its future is dropped before it's
+// done, e.g. by a timeout or on shutdown, so the waiters don't wait for
+// it forever
+struct DownloadGuard<'a> {
+  file: &'a AudioFile,
+  finished: bool,
+}
+
+impl Drop for DownloadGuard<'_> {
+  fn drop(&mut self) {
+    if self.finished {
+      return;
+    }
+    warn!("download of {} was cancelled", self.file.id);
+    std::fs::remove_file(&self.file.temp_path).ok();
+    self.file.state.send_replace(AudioFileState::Cancelled);
+  }
+}
+
+// the same video can be stored in several formats
+fn file_key(audio_id: &str, format: AudioFormat) -> String {
+  format!("{}.{}", audio_id, format.extension())
 }
 
 impl AudioFile {
-  fn new(base_dir: &Path, audio_id: &str) -> Self {
-    let file_path = base_dir.join(audio_id).with_extension("m4a");
-    let temp_path = base_dir.join(audio_id).with_extension("temp.m4a");
+  fn new(base_dir: &Path, audio_id: &str, format: AudioFormat) -> Self {
+    let ext = format.extension();
+    let file_path = base_dir.join(format!("{audio_id}.{ext}"));
+    let temp_path = base_dir.join(format!("{audio_id}.temp.{ext}"));
     Self {
       id: audio_id.to_string(),
+      format,
       path: file_path,
       temp_path,
-      state: Mutex::new(AudioFileState::New),
-      created_at: Instant::now(),
+      state: watch::channel(AudioFileState::New).0,
+      created_at: SystemTime::now(),
+      evicted: AtomicBool::new(false),
     }
   }
 
@@ -130,27 +476,317 @@ impl AudioFile {
     File::open(&self.path).await.map_err(Error::IO)
   }
 
+  pub fn is_ready(&self) -> bool {
+    *self.state.borrow() == AudioFileState::Ready
+  }
+
   pub async fn get_or_download<F, Fut>(&self, dl: F) -> Result<File>
   where
     F: FnOnce() -> Fut,
     Fut: Future<Output = Result<()>>,
   {
-    let mut guard = self.state.lock().await;
-    if let AudioFileState::Ready = &*guard {
-      info!("audio file already ready: {}", self.path.display());
-      return self.open().await;
+    self.download(dl).await?;
+    self.open().await
+  }
+
+  // run the download unless someone else already did, in which case
+  // wait for theirs to finish
+  pub async fn download<F, Fut>(&self, dl: F) -> Result<()>
+  where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<()>>,
+  {
+    loop {
+      let started = self.state.send_if_modified(|state| {
+        let start =
+          matches!(state, AudioFileState::New | AudioFileState::Cancelled);
+        if start {
+          *state = AudioFileState::Downloading;
+        }
+        start
+      });
+      if started {
+        break;
+      }
+      // take over a download that was cancelled while we waited
+      match self.wait_until_finished().await {
+        Err(Error::DownloadCancelled(_)) => continue,
+        result => return result,
+      }
+    }
+
+    let mut guard = DownloadGuard {
+      file: self,
+      finished: false,
     };
+    let result = match dl().await {
+      Ok(()) if !self.path.exists() => {
+        warn!(
+          "audio file not found after download: {}",
+          self.path.display()
+        );
+        Err(Error::AudioStream(self.id.clone()))
+      }
+      result => result,
+    };
+
+    match &result {
+      Ok(()) => {
+        info!("audio file downloaded: {}", self.path.display());
+        self.state.send_replace(AudioFileState::Ready);
+      }
+      Err(e) => {
+        let reason = match e {
+          Error::Ytdlp(reason) => Some(*reason),
+          _ => None,
+        };
+        self.state.send_replace(AudioFileState::Failed(reason));
+      }
+    }
+    guard.finished = true;
+    result
+  }
 
+  pub async fn wait_until_finished(&self) -> Result<()> {
+    let mut rx = self.state.subscribe();
+    let state = rx
+      .wait_for(|s| {
+        matches!(
+          s,
+          AudioFileState::Ready
+            | AudioFileState::Failed(_)
+            | AudioFileState::Cancelled
+        )
+      })
+      .await
+      .expect("sender is owned by the file");
 
-    dl().await?;
+    match *state {
+      AudioFileState::Ready => Ok(()),
+      AudioFileState::Failed(reason) => Err(self.failure(reason)),
+      AudioFileState::Cancelled => {
+        Err(Error::DownloadCancelled(self.id.clone()))
+      }
+      _ => unreachable!(),
+    }
+  }
 
-    if !self.path.exists() {
-      warn!("audio file not found after download: {}", self.path.display());
-      return Err(Error::AudioStream(self.id.clone()));
+  // whether the download is over, failed downloads are errors
+  fn is_finished(&self) -> Result<bool> {
+    match *self.state.borrow() {
+      AudioFileState::Ready => Ok(true),
+      AudioFileState::Failed(reason) => Err(self.failure(reason)),
+      AudioFileState::Cancelled => {
+        Err(Error::DownloadCancelled(self.id.clone()))
+      }
+      _ => Ok(false),
     }
+  }
 
-    info!("audio file downloaded: {}", self.path.display());
-    *guard = AudioFileState::Ready;
-    self.open().await
+  fn failure(&self, reason: Option<YtdlpError>) -> Error {
+    match reason {
+      Some(reason) => Error::Ytdlp(reason),
+      None => Error::AudioStream(self.id.clone()),
+    }
+  }
+
+  // wake up on a state change, or after a while to check the file size
+  async fn wait_for_progress(&self) {
+    let mut rx = self.state.subscribe();
+    let timeout = Duration::from_millis(200);
+    tokio::time::timeout(timeout, rx.changed()).await.ok();
+  }
+
+  // wait until the download has written its first bytes
+  pub async fn wait_until_started(&self) -> Result<()> {
+    while !self.is_finished()? {
+      let written = std::fs::metadata(&self.temp_path).map(|m| m.len());
+      if written.unwrap_or(0) > 0 {
+        return Ok(());
+      }
+      self.wait_for_progress().await;
+    }
+    Ok(())
+  }
+
+  // wait until the download has written `len` bytes or finished, and
+  // return how many bytes there are
+  pub async fn wait_for_len(&self, len: u64) -> Result<u64> {
+    loop {
+      let finished = self.is_finished()?;
+      let path = if finished {
+        &self.path
+      } else {
+        &self.temp_path
+      };
+      let written = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+      if finished || written >= len {
+        return Ok(written);
+      }
+      self.wait_for_progress().await;
+    }
+  }
+
+  // the partial file while downloading, the complete one afterwards
+  async fn open_growing(&self) -> Result<File> {
+    loop {
+      if self.is_finished()? {
+        return self.open().await;
+      }
+
+      match File::open(&self.temp_path).await {
+        Ok(file) => return Ok(file),
+        // not created yet, or just renamed to the final path
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+          self.wait_for_progress().await
+        }
+        Err(e) => return Err(e.into()),
+      }
+    }
+  }
+
+  // stream up to len bytes from the offset, following the file as the
+  // download appends to it. the stream ends with the download.
+  pub async fn growing_stream(
+    self: Arc<Self>,
+    offset: u64,
+    len: Option<u64>,
+  ) -> Result<BoxStream<'static, Result<Bytes>>> {
+    const CHUNK_SIZE: u64 = 64 * 1024;
+
+    let mut file = self.open_growing().await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+
+    let stream = futures::stream::try_unfold(
+      (file, self, len),
+      |(mut file, audio_file, remaining)| async move {
+        if remaining == Some(0) {
+          return Ok(None);
+        }
+
+        let size = remaining.unwrap_or(CHUNK_SIZE).min(CHUNK_SIZE);
+        let mut buf = vec![0; size as usize];
+        loop {
+          // checked before reading so no bytes written in between the
+          // read and the check get lost
+          let finished = audio_file.is_finished()?;
+          let n = file.read(&mut buf).await?;
+          if n > 0 {
+            buf.truncate(n);
+            let remaining = remaining.map(|r| r - n as u64);
+            let state = (file, audio_file, remaining);
+            return Ok(Some((Bytes::from(buf), state)));
+          }
+          if finished {
+            return Ok(None);
+          }
+          audio_file.wait_for_progress().await;
+        }
+      },
+    );
+
+    Ok(stream.boxed())
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_size() {
+    assert_eq!(parse_size("1024"), Some(1024));
+    assert_eq!(parse_size("500M"), Some(500 << 20));
+    assert_eq!(parse_size("2g"), Some(2 << 30));
+    assert_eq!(parse_size("lots"), None);
+  }
+
+  #[test]
+  fn test_reindex_and_evict() {
+    let dir = std::env::temp_dir()
+      .join(format!("audio-store-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("old.m4a"), [0; 100]).unwrap();
+    std::fs::write(dir.join("new.sponsor.mp3"), [0; 100]).unwrap();
+    std::fs::write(dir.join("partial.temp.m4a"), [0; 100]).unwrap();
+
+    let idle = Duration::from_secs(3600);
+    let mut store = AudioStore::with_quota(&dir, 150, idle);
+    assert!(!dir.join("partial.temp.m4a").exists());
+    assert_eq!(store.files.len(), 2);
+    assert_eq!(store.disk_usage(), 200);
+    store.files.get_mut("old.m4a").unwrap().last_access -= idle / 2;
+
+    // the least recently used file goes first
+    store.evict().unwrap();
+    assert!(!dir.join("old.m4a").exists());
+    assert!(dir.join("new.sponsor.mp3").exists());
+
+    // files in use are kept even if the quota can't be met
+    let in_use = store.files["new.sponsor.mp3"].file.clone();
+    store.quota = 50;
+    assert!(matches!(store.evict(), Err(Error::StorageFull(_))));
+    drop(in_use);
+    store.evict().unwrap();
+    assert!(!dir.join("new.sponsor.mp3").exists());
+
+    std::fs::remove_dir_all(&dir).ok();
+  }
+
+  #[tokio::test]
+  async fn test_growing_stream() {
+    use std::io::Write as _;
+
+    let dir = std::env::temp_dir()
+      .join(format!("audio-store-test-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = Arc::new(AudioFile::new(&dir, "growing", AudioFormat::M4a));
+    file.state.send_replace(AudioFileState::Downloading);
+    std::fs::write(&file.temp_path, b"hello ").unwrap();
+
+    let stream = file.clone().growing_stream(0, None).await.unwrap();
+    let writer = {
+      let file = file.clone();
+      async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut temp = std::fs::OpenOptions::new()
+          .append(true)
+          .open(&file.temp_path)
+          .unwrap();
+        temp.write_all(b"world").unwrap();
+        std::fs::rename(&file.temp_path, &file.path).unwrap();
+        file.state.send_replace(AudioFileState::Ready);
+      }
+    };
+
+    let (chunks, ()) = tokio::join!(
+      stream.map(|chunk| chunk.unwrap()).collect::<Vec<_>>(),
+      writer
+    );
+    assert_eq!(chunks.concat(), b"hello world");
+
+    std::fs::remove_dir_all(&dir).ok();
+  }
+
+  #[tokio::test]
+  async fn test_cancelled_download() {
+    let dir = std::env::temp_dir();
+    let file = Arc::new(AudioFile::new(&dir, "cancelled", AudioFormat::M4a));
+
+    // a download that never finishes, dropped by a timeout
+    let hanging = file.download(std::future::pending);
+    let timeout = Duration::from_millis(20);
+    assert!(tokio::time::timeout(timeout, hanging).await.is_err());
+    assert_eq!(*file.state.borrow(), AudioFileState::Cancelled);
+    assert!(matches!(
+      file.wait_until_finished().await,
+      Err(Error::DownloadCancelled(_))
+    ));
+
+    // the next one starts it over
+    let result = file
+      .download(|| async { Err(Error::AudioStream("retried".into())) })
+      .await;
+    assert!(matches!(result, Err(Error::AudioStream(id)) if id == "retried"));
   }
 }
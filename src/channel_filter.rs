@@ -0,0 +1,435 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, LazyLock, Mutex},
+  time::{Duration, SystemTime},
+};
+
+use futures::future::join_all;
+use lru_time_cache::LruCache;
+use tracing::{info, warn};
+
+use crate::{
+  config::{ChannelFilterConfig, ChannelList, Config},
+  feed,
+  podcast::Podcast,
+  video_info, Error, Result,
+};
+
+// the filter as of the last modification time of CONFIG_FILE
+type Loaded = Option<(SystemTime, Arc<ChannelFilterConfig>)>;
+
+static LOADED: LazyLock<Mutex<Loaded>> = LazyLock::new(Default::default);
+
+// the listed handles by lowercased handle, looked up when the filter is
+// loaded
+type HandleIds = HashMap<String, Resolution>;
+
+static HANDLE_IDS: LazyLock<Mutex<HandleIds>> = LazyLock::new(Default::default);
+
+// one lookup at a time, the requests coming in meanwhile wait for it
+static RESOLVING: LazyLock<tokio::sync::Mutex<()>> =
+  LazyLock::new(Default::default);
+
+// failed handle lookups are tried again after this long
+const HANDLE_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone)]
+enum Resolution {
+  Id(String),
+  Failed,
+}
+
+// where the videos in the harvested feeds came from, so the audio
+// requests can be checked without looking them up
+static VIDEO_SOURCES: LazyLock<Mutex<LruCache<String, VideoSource>>> =
+  LazyLock::new(|| {
+    Mutex::new(LruCache::with_expiry_duration_and_capacity(
+      Duration::from_secs(7 * 24 * 60 * 60),
+      10_000,
+    ))
+  });
+
+#[derive(Debug, Clone, Default)]
+struct VideoSource {
+  channel_id: Option<String>,
+  handle: Option<String>,
+  playlist_id: Option<String>,
+}
+
+// the filter in CONFIG_FILE, reloaded when the file changes. a broken
+// file keeps the last good filter.
+fn current() -> Arc<ChannelFilterConfig> {
+  let Ok(path) = std::env::var("CONFIG_FILE") else {
+    return Default::default();
+  };
+  let modified = std::fs::metadata(&path)
+    .and_then(|m| m.modified())
+    .unwrap_or(SystemTime::UNIX_EPOCH);
+
+  let mut loaded = LOADED.lock().unwrap();
+  if let Some((at, filter)) = &*loaded {
+    if *at == modified {
+      return filter.clone();
+    }
+  }
+
+  let filter = match Config::load(&path) {
+    Ok(config) => {
+      if loaded.is_some() {
+        info!("reloaded the channel filter from {path}");
+      }
+      let filter = Arc::new(config.channel_filter);
+      tokio::spawn(keep_resolving(filter.clone()));
+      filter
+    }
+    Err(e) => {
+      warn!("failed reloading the channel filter from {path}: {e}");
+      loaded.as_ref().map(|(_, f)| f.clone()).unwrap_or_default()
+    }
+  };
+  *loaded = Some((modified, filter.clone()));
+  filter
+}
+
+fn normalize_handle(handle: &str) -> String {
+  handle.trim_start_matches('@').to_lowercase()
+}
+
+// the current filter, with its handles looked up. only the first
+// lookup of a handle is waited for, the failed ones are retried in the
+// background.
+async fn filter() -> Arc<ChannelFilterConfig> {
+  let filter = current();
+  if !pending(&filter, &HANDLE_IDS.lock().unwrap(), false).is_empty() {
+    resolve_handles(&filter, false).await;
+  }
+  filter
+}
+
+// look up the handles of a newly loaded filter, and retry the failed
+// lookups until they succeed or the filter is replaced
+async fn keep_resolving(filter: Arc<ChannelFilterConfig>) {
+  resolve_handles(&filter, false).await;
+  loop {
+    if pending(&filter, &HANDLE_IDS.lock().unwrap(), true).is_empty() {
+      return;
+    }
+    tokio::time::sleep(HANDLE_RETRY_AFTER).await;
+    if !is_current(&filter) {
+      return;
+    }
+    resolve_handles(&filter, true).await;
+  }
+}
+
+fn is_current(filter: &Arc<ChannelFilterConfig>) -> bool {
+  let loaded = LOADED.lock().unwrap();
+  loaded.as_ref().is_some_and(|(_, f)| Arc::ptr_eq(f, filter))
+}
+
+// the handles not looked up yet, and with `retry` the failed ones
+fn pending<'a>(
+  filter: &'a ChannelFilterConfig,
+  ids: &HandleIds,
+  retry: bool,
+) -> Vec<&'a String> {
+  let handles = filter.allow.handles.iter().chain(&filter.deny.handles);
+  handles
+    .filter(|h| match ids.get(&normalize_handle(h)) {
+      Some(Resolution::Id(_)) => false,
+      Some(Resolution::Failed) => retry,
+      None => true,
+    })
+    .collect()
+}
+
+async fn resolve_handles(filter: &ChannelFilterConfig, retry: bool) {
+  let _resolving = RESOLVING.lock().await;
+  // looked up while we were waiting
+  let pending = pending(filter, &HANDLE_IDS.lock().unwrap(), retry);
+  join_all(pending.into_iter().map(|h| resolve_handle(h))).await;
+}
+
+async fn resolve_handle(handle: &str) {
+  let resolution = match feed::resolve_handle(handle).await {
+    Ok(id) => Resolution::Id(id),
+    Err(e) => {
+      warn!("failed resolving channel handle {handle}: {e}");
+      Resolution::Failed
+    }
+  };
+  let key = normalize_handle(handle);
+  HANDLE_IDS.lock().unwrap().insert(key, resolution);
+}
+
+fn handle_id<'a>(ids: &'a HandleIds, handle: &str) -> Option<&'a String> {
+  match ids.get(&normalize_handle(handle)) {
+    Some(Resolution::Id(id)) => Some(id),
+    _ => None,
+  }
+}
+
+impl ChannelList {
+  fn matches(&self, ids: &HandleIds, source: &VideoSource) -> bool {
+    if let Some(playlist_id) = &source.playlist_id {
+      if self.playlists.contains(playlist_id) {
+        return true;
+      }
+    }
+
+    if let Some(handle) = &source.handle {
+      let handle = normalize_handle(handle);
+      if self.handles.iter().any(|h| normalize_handle(h) == handle) {
+        return true;
+      }
+    }
+
+    let Some(channel_id) = &source.channel_id else {
+      return false;
+    };
+    if self.channels.contains(channel_id) {
+      return true;
+    }
+    self
+      .handles
+      .iter()
+      .filter_map(|h| handle_id(ids, h))
+      .any(|id| id == channel_id)
+  }
+
+  fn has_unresolved(&self, ids: &HandleIds) -> bool {
+    self.handles.iter().any(|h| handle_id(ids, h).is_none())
+  }
+}
+
+fn check(
+  filter: &ChannelFilterConfig,
+  ids: &HandleIds,
+  source: &VideoSource,
+  what: String,
+) -> Result<()> {
+  if filter.deny.matches(ids, source) {
+    return Err(Error::Blocked(what));
+  }
+  if !filter.allow.is_empty() && !filter.allow.matches(ids, source) {
+    // the channel may be behind a handle that couldn't be looked up
+    if filter.allow.has_unresolved(ids) {
+      return Err(Error::HandlesUnresolved(what));
+    }
+    return Err(Error::Blocked(what));
+  }
+  Ok(())
+}
+
+pub async fn check_channel(
+  channel_id: &str,
+  handle: Option<&str>,
+) -> Result<()> {
+  let source = VideoSource {
+    channel_id: Some(channel_id.to_string()),
+    handle: handle.map(str::to_string),
+    playlist_id: None,
+  };
+  let filter = filter().await;
+  let ids = HANDLE_IDS.lock().unwrap();
+  check(&filter, &ids, &source, format!("channel {channel_id}"))
+}
+
+pub async fn check_playlist(playlist_id: &str) -> Result<()> {
+  let source = VideoSource {
+    playlist_id: Some(playlist_id.to_string()),
+    ..Default::default()
+  };
+  let filter = filter().await;
+  let ids = HANDLE_IDS.lock().unwrap();
+  check(&filter, &ids, &source, format!("playlist {playlist_id}"))
+}
+
+// check the channel of the video, looking it up unless it was seen in a
+// harvested feed
+pub async fn check_video(video_id: &str) -> Result<()> {
+  let filter = filter().await;
+  if filter.allow.is_empty() && filter.deny.is_empty() {
+    return Ok(());
+  }
+
+  let cached = VIDEO_SOURCES.lock().unwrap().get(video_id).cloned();
+  let source = match cached {
+    Some(source) if source.channel_id.is_some() => source,
+    // the playlist alone doesn't tell whether the channel is denied
+    cached => {
+      let info = video_info::fetch(video_id).await?;
+      let source = VideoSource {
+        channel_id: info.channel_id.clone(),
+        handle: info.uploader_id.clone(),
+        playlist_id: cached.and_then(|s| s.playlist_id),
+      };
+      let key = video_id.to_string();
+      VIDEO_SOURCES.lock().unwrap().insert(key, source.clone());
+      source
+    }
+  };
+
+  let ids = HANDLE_IDS.lock().unwrap();
+  check(&filter, &ids, &source, format!("video {video_id}"))
+}
+
+// remember the channel of the videos in the feed
+pub fn record_channel(channel_id: &str, podcast: &Podcast) {
+  let mut sources = VIDEO_SOURCES.lock().unwrap();
+  for episode in &podcast.episodes {
+    let source = sources
+      .entry(episode.video_id.clone())
+      .or_insert_with(Default::default);
+    source.channel_id = Some(channel_id.to_string());
+  }
+}
+
+// remember the playlist and channels of the videos in the feed
+pub fn record_playlist(playlist_id: &str, podcast: &Podcast) {
+  let mut sources = VIDEO_SOURCES.lock().unwrap();
+  for episode in &podcast.episodes {
+    let source = sources
+      .entry(episode.video_id.clone())
+      .or_insert_with(Default::default);
+    source.playlist_id = Some(playlist_id.to_string());
+    if episode.channel_id.is_some() {
+      source.channel_id = episode.channel_id.clone();
+    }
+  }
+}
+
+// drop the episodes of denied channels from a feed mixing channels
+pub async fn remove_denied(podcast: &mut Podcast) {
+  let filter = filter().await;
+  let ids = HANDLE_IDS.lock().unwrap();
+  remove_denied_by(&filter.deny, &ids, podcast)
+}
+
+fn remove_denied_by(
+  deny: &ChannelList,
+  ids: &HandleIds,
+  podcast: &mut Podcast,
+) {
+  if deny.is_empty() {
+    return;
+  }
+
+  podcast.episodes.retain(|episode| {
+    let source = VideoSource {
+      channel_id: episode.channel_id.clone(),
+      ..Default::default()
+    };
+    !deny.matches(ids, &source)
+  });
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::podcast::Episode;
+
+  #[test]
+  fn test_check() {
+    let list = |channels: &[&str], playlists: &[&str]| ChannelList {
+      channels: channels.iter().map(|s| s.to_string()).collect(),
+      handles: vec![],
+      playlists: playlists.iter().map(|s| s.to_string()).collect(),
+    };
+    let source = |channel_id: &str, playlist_id: Option<&str>| VideoSource {
+      channel_id: Some(channel_id.to_string()),
+      handle: None,
+      playlist_id: playlist_id.map(str::to_string),
+    };
+    let mut ids = HandleIds::new();
+    let allowed =
+      |filter: &ChannelFilterConfig, ids: &HandleIds, source: VideoSource| {
+        check(filter, ids, &source, String::new()).is_ok()
+      };
+
+    // everything goes without lists
+    let filter = ChannelFilterConfig::default();
+    assert!(allowed(&filter, &ids, source("UCa", None)));
+
+    let filter = ChannelFilterConfig {
+      allow: list(&["UCa"], &["PLa"]),
+      deny: list(&["UCb"], &[]),
+    };
+    assert!(allowed(&filter, &ids, source("UCa", None)));
+    assert!(!allowed(&filter, &ids, source("UCc", None)));
+    // a video of an allowed playlist
+    assert!(allowed(&filter, &ids, source("UCc", Some("PLa"))));
+    // unless its channel is denied
+    assert!(!allowed(&filter, &ids, source("UCb", Some("PLa"))));
+
+    let filter = ChannelFilterConfig {
+      allow: ChannelList::default(),
+      deny: ChannelList {
+        handles: vec!["@Spam".into()],
+        ..Default::default()
+      },
+    };
+    let spam = VideoSource {
+      handle: Some("spam".into()),
+      ..Default::default()
+    };
+    assert!(!allowed(&filter, &ids, spam));
+
+    let filter = ChannelFilterConfig {
+      allow: ChannelList {
+        handles: vec!["@Known".into(), "@unknown".into()],
+        ..Default::default()
+      },
+      deny: ChannelList::default(),
+    };
+    ids.insert("known".into(), Resolution::Id("UCk".into()));
+    ids.insert("unknown".into(), Resolution::Failed);
+    assert!(allowed(&filter, &ids, source("UCk", None)));
+    // the channel may be the one of the handle that failed to resolve
+    let result = check(&filter, &ids, &source("UCc", None), String::new());
+    assert!(matches!(result, Err(Error::HandlesUnresolved(_))));
+  }
+
+  #[test]
+  fn test_pending() {
+    let filter = ChannelFilterConfig {
+      allow: ChannelList {
+        handles: vec!["@Known".into(), "@failed".into(), "@new".into()],
+        ..Default::default()
+      },
+      deny: ChannelList::default(),
+    };
+    let mut ids = HandleIds::new();
+    ids.insert("known".into(), Resolution::Id("UCk".into()));
+    ids.insert("failed".into(), Resolution::Failed);
+
+    // requests only wait for the first lookup
+    assert_eq!(pending(&filter, &ids, false), ["@new"]);
+    assert_eq!(pending(&filter, &ids, true), ["@failed", "@new"]);
+  }
+
+  #[test]
+  fn test_remove_denied() {
+    let episode = |video_id: &str, channel_id: Option<&str>| Episode {
+      video_id: video_id.to_string(),
+      channel_id: channel_id.map(str::to_string),
+      ..Default::default()
+    };
+    let mut podcast = Podcast {
+      episodes: vec![
+        episode("v1", Some("UCa")),
+        episode("v2", Some("UCb")),
+        episode("v3", None),
+      ],
+      ..Default::default()
+    };
+    let deny = ChannelList {
+      channels: vec!["UCb".into()],
+      ..Default::default()
+    };
+
+    remove_denied_by(&deny, &HandleIds::new(), &mut podcast);
+    let ids: Vec<_> = podcast.episodes.iter().map(|e| &e.video_id).collect();
+    assert_eq!(ids, ["v1", "v3"]);
+  }
+}
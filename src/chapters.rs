@@ -0,0 +1,153 @@
+use std::sync::LazyLock;
+
+use axum::{extract::Path, response::IntoResponse};
+use regex::Regex;
+use reqwest::header;
+use serde::Serialize;
+
+use crate::{
+  audio_signature::{self, SignedAudio},
+  channel_filter,
+  rate_limit::DOWNLOAD_RATE_LIMIT,
+  subscriber::AudioAccess,
+  video_info::{self, VideoInfo},
+  Result, INSTANCE_PUBLIC_URL,
+};
+
+// https://github.com/Podcastindex-org/podcast-namespace/blob/main/chapters/jsonChapters.md
+const CHAPTERS_MIME_TYPE: &str = "application/json+chapters";
+
+#[derive(Debug, Serialize)]
+struct ChaptersDoc {
+  version: &'static str,
+  chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Chapter {
+  start_time: f64,
+  title: String,
+}
+
+pub fn chapters_url(video_id: &str) -> String {
+  let url = format!("{}/chapters/{}", &*INSTANCE_PUBLIC_URL, video_id);
+  match audio_signature::sign(video_id) {
+    Some((name, signature)) => format!("{url}?{name}={signature}"),
+    None => url,
+  }
+}
+
+pub async fn get_chapters(
+  Path(video_id): Path<String>,
+  // guarded like the audio, as looking up the video runs yt-dlp
+  (_, access): (SignedAudio, AudioAccess),
+) -> Result<impl IntoResponse> {
+  eprintln!("client requesting chapters: {}", video_id);
+
+  if !video_info::is_cached(&video_id) {
+    DOWNLOAD_RATE_LIMIT.check(access.client(), 1)?;
+  }
+  channel_filter::check_video(&video_id).await?;
+
+  let info = video_info::fetch(&video_id).await?;
+  let doc = ChaptersDoc {
+    version: "1.2.0",
+    chapters: chapters_of(&info),
+  };
+  let body = serde_json::to_vec(&doc)?;
+
+  Ok(([(header::CONTENT_TYPE, CHAPTERS_MIME_TYPE)], body))
+}
+
+fn chapters_of(info: &VideoInfo) -> Vec<Chapter> {
+  match &info.chapters {
+    Some(chapters) if !chapters.is_empty() => chapters
+      .iter()
+      .map(|c| Chapter {
+        start_time: c.start_time,
+        title: c.title.clone(),
+      })
+      .collect(),
+    _ => parse_description(info.description.as_deref().unwrap_or_default()),
+  }
+}
+
+// youtube turns lines like "12:34 Topic" in the description into
+// chapters when the first one starts at 0:00 and the rest are ascending.
+fn parse_description(description: &str) -> Vec<Chapter> {
+  static LINE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+      r"^\s*[(\[]?((?:\d{1,2}:)?\d{1,2}:\d{2})[)\]]?\s*[-–—:|]?\s*(.+?)\s*$",
+    )
+    .unwrap()
+  });
+
+  let chapters: Vec<Chapter> = description
+    .lines()
+    .filter_map(|line| LINE_REGEX.captures(line))
+    .filter_map(|captures| {
+      Some(Chapter {
+        start_time: parse_timestamp(&captures[1])?,
+        title: captures[2].to_string(),
+      })
+    })
+    .collect();
+
+  let starts_at_zero = chapters.first().is_some_and(|c| c.start_time == 0.0);
+  let ascending = chapters
+    .windows(2)
+    .all(|w| w[0].start_time < w[1].start_time);
+  if chapters.len() < 2 || !starts_at_zero || !ascending {
+    return vec![];
+  }
+
+  chapters
+}
+
+// "1:02:03" or "02:03" to seconds
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+  timestamp.split(':').try_fold(0.0, |secs, part| {
+    Some(secs * 60.0 + part.parse::<u32>().ok()? as f64)
+  })
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_description() {
+    let description = "\
+      A talk about things.\n\
+      \n\
+      0:00 Intro\n\
+      (01:30) - The problem\n\
+      [1:02:03] Q&A \n\
+      Thanks for watching at 99:99!";
+
+    let chapters = parse_description(description);
+    assert_eq!(
+      chapters,
+      [
+        Chapter {
+          start_time: 0.0,
+          title: "Intro".into()
+        },
+        Chapter {
+          start_time: 90.0,
+          title: "The problem".into()
+        },
+        Chapter {
+          start_time: 3723.0,
+          title: "Q&A".into()
+        },
+      ]
+    );
+
+    // not starting at 0:00
+    assert_eq!(parse_description("1:00 One\n2:00 Two"), []);
+    // not ascending
+    assert_eq!(parse_description("0:00 One\n2:00 Two\n1:00 Three"), []);
+  }
+}
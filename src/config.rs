@@ -0,0 +1,235 @@
+use std::{sync::LazyLock, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+// the backends to try and in which order, read from the toml file at
+// CONFIG_FILE. see config.example.toml.
+pub static CONFIG: LazyLock<Config> =
+  LazyLock::new(|| match std::env::var("CONFIG_FILE") {
+    Ok(path) => Config::load(&path).expect("Invalid CONFIG_FILE"),
+    Err(_) => Config::default(),
+  });
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+  #[serde(default = "default_harvestors")]
+  pub harvestors: Vec<Backend<HarvestorConfig>>,
+  #[serde(default = "default_extractors")]
+  pub extractors: Vec<Backend<ExtractorConfig>>,
+  // picked up again when the file changes, see channel_filter
+  #[serde(default)]
+  pub channel_filter: ChannelFilterConfig,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      harvestors: default_harvestors(),
+      extractors: default_extractors(),
+      channel_filter: ChannelFilterConfig::default(),
+    }
+  }
+}
+
+impl Config {
+  pub fn load(path: &str) -> Result<Self> {
+    let content = std::fs::read_to_string(path)?;
+    Self::parse(&content)
+  }
+
+  fn parse(content: &str) -> Result<Self> {
+    let config: Self =
+      toml::from_str(content).map_err(|e| Error::Config(e.to_string()))?;
+
+    if config.extractors.is_empty() {
+      return Err(Error::Config("no extractor configured".into()));
+    }
+    Ok(config)
+  }
+
+  // whether an invidious instance has to be discovered
+  pub fn uses_invidious(&self) -> bool {
+    let harvestor = self
+      .harvestors
+      .iter()
+      .any(|h| matches!(h.backend, HarvestorConfig::Invidious));
+    let extractor = self
+      .extractors
+      .iter()
+      .any(|e| matches!(e.backend, ExtractorConfig::Invidious));
+    harvestor || extractor
+  }
+}
+
+// only the allowed channels are served when any are listed, and never
+// the denied ones
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelFilterConfig {
+  #[serde(default)]
+  pub allow: ChannelList,
+  #[serde(default)]
+  pub deny: ChannelList,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelList {
+  #[serde(default)]
+  pub channels: Vec<String>,
+  // with or without the leading @
+  #[serde(default)]
+  pub handles: Vec<String>,
+  #[serde(default)]
+  pub playlists: Vec<String>,
+}
+
+impl ChannelList {
+  pub fn is_empty(&self) -> bool {
+    self.channels.is_empty()
+      && self.handles.is_empty()
+      && self.playlists.is_empty()
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Backend<T> {
+  #[serde(flatten)]
+  pub backend: T,
+  // in seconds, unlimited if not set
+  #[serde(default)]
+  timeout: Option<u64>,
+}
+
+impl<T> Backend<T> {
+  fn new(backend: T) -> Self {
+    Self {
+      backend,
+      timeout: None,
+    }
+  }
+
+  pub fn timeout(&self) -> Option<Duration> {
+    self.timeout.map(Duration::from_secs)
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HarvestorConfig {
+  Ytdlp {
+    // how many of the latest videos to include
+    #[serde(default = "default_max_episodes")]
+    max_episodes: usize,
+  },
+  // only used when a piped instance is available
+  RssPiped,
+  RssYtextract,
+  Invidious,
+}
+
+impl HarvestorConfig {
+  pub fn name(&self) -> &'static str {
+    match self {
+      HarvestorConfig::Ytdlp { .. } => "ytdlp",
+      HarvestorConfig::RssPiped => "rss_piped",
+      HarvestorConfig::RssYtextract => "rss_ytextract",
+      HarvestorConfig::Invidious => "invidious",
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ExtractorConfig {
+  YtdlpFile,
+  YtdlpStream,
+  YtdlpProxy,
+  Piped,
+  Rustube,
+  Invidious,
+}
+
+impl ExtractorConfig {
+  pub fn name(&self) -> &'static str {
+    match self {
+      ExtractorConfig::YtdlpFile => "ytdlp_file",
+      ExtractorConfig::YtdlpStream => "ytdlp_stream",
+      ExtractorConfig::YtdlpProxy => "ytdlp_proxy",
+      ExtractorConfig::Piped => "piped",
+      ExtractorConfig::Rustube => "rustube",
+      ExtractorConfig::Invidious => "invidious",
+    }
+  }
+}
+
+fn default_max_episodes() -> usize {
+  20
+}
+
+fn default_harvestors() -> Vec<Backend<HarvestorConfig>> {
+  vec![
+    Backend::new(HarvestorConfig::Ytdlp {
+      max_episodes: default_max_episodes(),
+    }),
+    Backend::new(HarvestorConfig::RssPiped),
+  ]
+}
+
+fn default_extractors() -> Vec<Backend<ExtractorConfig>> {
+  vec![Backend::new(ExtractorConfig::YtdlpFile)]
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_config() {
+    let config = Config::parse(
+      r#"
+      [[harvestors]]
+      backend = "ytdlp"
+      max_episodes = 50
+      timeout = 60
+
+      [[extractors]]
+      backend = "ytdlp_stream"
+
+      [[extractors]]
+      backend = "piped"
+      timeout = 10
+
+      [channel_filter.deny]
+      handles = ["@spam"]
+      "#,
+    )
+    .unwrap();
+
+    assert!(matches!(
+      config.harvestors[0].backend,
+      HarvestorConfig::Ytdlp { max_episodes: 50 }
+    ));
+    assert_eq!(
+      config.harvestors[0].timeout(),
+      Some(Duration::from_secs(60))
+    );
+    assert_eq!(config.harvestors.len(), 1);
+    let names: Vec<_> =
+      config.extractors.iter().map(|e| e.backend.name()).collect();
+    assert_eq!(names, ["ytdlp_stream", "piped"]);
+    assert!(config.channel_filter.allow.is_empty());
+    assert_eq!(config.channel_filter.deny.handles, ["@spam"]);
+
+    // defaults apply to the missing chains
+    let config = Config::parse("").unwrap();
+    assert_eq!(config.harvestors.len(), 2);
+    assert_eq!(config.extractors[0].backend.name(), "ytdlp_file");
+
+    assert!(Config::parse("[[extractors]]\nbackend = \"vlc\"").is_err());
+    assert!(Config::parse("extractors = []").is_err());
+  }
+}
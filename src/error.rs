@@ -1,6 +1,8 @@
 use atom_syndication::Entry;
 use axum::response::{IntoResponse, Response};
 
+use crate::extractor::YtdlpError;
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(thiserror::Error, Debug)]
@@ -30,6 +32,8 @@ pub enum Error {
   Invidious(&'static str),
   #[error("unable to get audio stream: {0}")]
   AudioStream(String),
+  #[error("yt-dlp: {0}")]
+  Ytdlp(#[from] YtdlpError),
   #[error("rustube error: {0}")]
   Rustube(#[from] rustube::Error),
   #[error("extraction error")]
@@ -46,6 +50,42 @@ pub enum Error {
   StorageFull(String),
   #[error("file pending: {0}")]
   FilePending(String),
+  #[error("audio store is unavailable: {0}")]
+  AudioStoreUnavailable(String),
+  #[error("download cancelled: {0}")]
+  DownloadCancelled(String),
+  #[error("unable to get sponsorblock segments: {0}")]
+  SegmentsUnavailable(String),
+  #[error("unable to get video info: {0}")]
+  VideoInfo(String),
+  #[error("unable to harvest: {0}")]
+  Harvest(String),
+  #[error("invalid config: {0}")]
+  Config(String),
+  #[error("{0} timed out")]
+  BackendTimeout(&'static str),
+  #[error("invalid bundle: {0}")]
+  InvalidBundle(&'static str),
+  #[error("transcript not found: {0}")]
+  TranscriptNotFound(String),
+  #[error("unauthorized")]
+  Unauthorized,
+  #[error("missing or invalid subscriber token")]
+  InvalidToken,
+  #[error("audio url is not signed by this instance")]
+  InvalidSignature,
+  #[error("too many requests, retry in {} seconds", .0.as_secs())]
+  TooManyRequests(std::time::Duration),
+  #[error("{0} is not served by this instance")]
+  Blocked(String),
+  #[error("unable to look up the channel handles to check {0}")]
+  HandlesUnresolved(String),
+  #[error("shutting down")]
+  ShuttingDown,
+  #[error("admin page is disabled")]
+  AdminDisabled,
+  #[error("invalid sponsorblock category: {0}")]
+  InvalidSponsorBlockCategory(String),
 }
 
 impl IntoResponse for Error {
@@ -63,10 +103,57 @@ impl IntoResponse for Error {
       ParseURL(_) => StatusCode::BAD_GATEWAY,
       InvalidHTML(_) => StatusCode::BAD_GATEWAY,
       UnsupportedURL(_, _) => StatusCode::BAD_REQUEST,
+      InvalidSponsorBlockCategory(_) => StatusCode::BAD_REQUEST,
+      TranscriptNotFound(_) => StatusCode::NOT_FOUND,
+      InvalidBundle(_) => StatusCode::BAD_REQUEST,
+      StorageFull(_) => StatusCode::INSUFFICIENT_STORAGE,
+      AudioStoreUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+      DownloadCancelled(_) => StatusCode::SERVICE_UNAVAILABLE,
+      SegmentsUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+      ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+      BackendTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
       HTTP(_) => StatusCode::BAD_GATEWAY,
+      Unauthorized => StatusCode::UNAUTHORIZED,
+      InvalidToken => StatusCode::UNAUTHORIZED,
+      InvalidSignature => StatusCode::FORBIDDEN,
+      Blocked(_) => StatusCode::FORBIDDEN,
+      HandlesUnresolved(_) => StatusCode::SERVICE_UNAVAILABLE,
+      TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+      AdminDisabled => StatusCode::NOT_FOUND,
+      Ytdlp(e) => match e {
+        YtdlpError::Private
+        | YtdlpError::MembersOnly
+        | YtdlpError::AgeRestricted => StatusCode::FORBIDDEN,
+        YtdlpError::GeoBlocked => StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+        YtdlpError::Removed => StatusCode::NOT_FOUND,
+        YtdlpError::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        YtdlpError::NotStarted(_) | YtdlpError::Network => {
+          StatusCode::SERVICE_UNAVAILABLE
+        }
+      },
       _ => StatusCode::INTERNAL_SERVER_ERROR,
     };
 
-    (code, self.to_string()).into_response()
+    crate::admin::record_error(code, &self);
+
+    let mut response = (code, self.to_string()).into_response();
+    if let Unauthorized = &self {
+      let value = http::HeaderValue::from_static("Basic realm=\"admin\"");
+      response
+        .headers_mut()
+        .insert(http::header::WWW_AUTHENTICATE, value);
+    }
+    let retry_after = match &self {
+      Ytdlp(e) => e.retry_after(),
+      TooManyRequests(wait) => Some(*wait),
+      _ => None,
+    };
+    if let Some(retry_after) = retry_after {
+      let value = http::HeaderValue::from(retry_after.as_secs());
+      response
+        .headers_mut()
+        .insert(http::header::RETRY_AFTER, value);
+    }
+    response
   }
 }
@@ -1,20 +1,35 @@
+mod ffmpeg;
+mod invidious;
 mod piped;
 mod rustube;
+mod transcode;
+mod ytdlp_error;
 mod ytdlp_file;
 mod ytdlp_proxy;
 mod ytdlp_stream;
 
 use async_trait::async_trait;
+use std::sync::Arc;
+
 use axum::body::Bytes;
 use futures::stream::BoxStream;
 use tokio::fs::File;
 
-use crate::Result;
+use crate::{
+  audio_store::{AudioFile, AudioStoreRef},
+  config::ExtractorConfig,
+  invidious::InvidiousInstance,
+  piped::PipedInstance,
+  Result,
+};
 
-#[allow(unused)]
 pub use self::rustube::Rustube;
+pub use invidious::Invidious;
 pub use piped::Piped;
+pub use transcode::Transcode;
+pub use ytdlp_error::{classify, YtdlpError};
 pub use ytdlp_file::YtdlpFile;
+pub use ytdlp_proxy::YtdlpProxy;
 pub use ytdlp_stream::YtdlpStream;
 
 pub enum Extraction {
@@ -32,9 +47,30 @@ pub enum Extraction {
     file: File,
     mime_type: String,
   },
+  // a file that is still being downloaded
+  GrowingFile {
+    file: Arc<AudioFile>,
+    mime_type: String,
+  },
 }
 
 #[async_trait]
 pub trait Extractor {
   async fn extract(&self, video_id: &str) -> Result<Extraction>;
 }
+
+pub fn build<'a>(
+  config: &ExtractorConfig,
+  audio_store: &Arc<AudioStoreRef>,
+  piped: &'a PipedInstance,
+  invidious: &'a InvidiousInstance,
+) -> Box<dyn Extractor + Send + Sync + 'a> {
+  match config {
+    ExtractorConfig::YtdlpFile => Box::new(YtdlpFile::new(audio_store.clone())),
+    ExtractorConfig::YtdlpStream => Box::new(YtdlpStream),
+    ExtractorConfig::YtdlpProxy => Box::new(YtdlpProxy),
+    ExtractorConfig::Piped => Box::new(Piped(piped)),
+    ExtractorConfig::Rustube => Box::new(Rustube),
+    ExtractorConfig::Invidious => Box::new(Invidious(invidious)),
+  }
+}
@@ -0,0 +1,45 @@
+use std::{ffi::OsStr, path::Path};
+
+use tokio::process::Command;
+
+use crate::util::FFMPEG_MUTEX;
+use crate::{Error, Result};
+
+// run ffmpeg to convert the input file with the given output options.
+// requires ffmpeg executable to be in PATH.
+pub async fn ffmpeg<I, S>(input: &Path, args: I, output: &Path) -> Result<()>
+where
+  I: IntoIterator<Item = S>,
+  S: AsRef<OsStr>,
+{
+  let mut cmd = Command::new("ffmpeg");
+  cmd
+    .arg("-nostdin")
+    .arg("-y")
+    .args(["-v", "error"])
+    .arg("-i")
+    .arg(input)
+    // drop embedded cover art
+    .arg("-vn")
+    .args(args)
+    .arg(output)
+    .kill_on_drop(true);
+
+  let guard = FFMPEG_MUTEX.acquire().await.unwrap();
+  let result = cmd.output().await;
+  drop(guard);
+  let output_status = result?;
+
+  if !output_status.status.success() {
+    let stderr = String::from_utf8_lossy(&output_status.stderr);
+    let message = format!(
+      "ffmpeg exited with code ({:?}): {}",
+      output_status.status.code(),
+      stderr
+    );
+    std::fs::remove_file(output).ok();
+    return Err(Error::AudioStream(message));
+  }
+
+  Ok(())
+}
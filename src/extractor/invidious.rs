@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{
+  invidious::{InvidiousInstance, InvidiousInstanceRepo},
+  Error, Result,
+};
+
+use super::{Extraction, Extractor};
+
+pub struct Invidious<'a>(pub &'a InvidiousInstance);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InvidiousVideo {
+  adaptive_formats: Vec<AdaptiveFormat>,
+}
+
+#[derive(Deserialize)]
+struct AdaptiveFormat {
+  url: String,
+  #[serde(rename = "type")]
+  mime_type: String,
+  bitrate: String,
+}
+
+#[async_trait]
+impl Extractor for Invidious<'_> {
+  async fn extract(&self, video_id: &str) -> Result<Extraction> {
+    // local=true makes the instance proxy the stream, googlevideo urls
+    // only work from the ip address that requested them
+    let url = format!("{}?local=true", self.0.video_url(video_id));
+    let video: InvidiousVideo = reqwest::Client::new()
+      .get(url)
+      .send()
+      .await
+      .and_then(|resp| resp.error_for_status())
+      .map_err(InvidiousInstanceRepo::notify_update)?
+      .json()
+      .await?;
+
+    let format = video
+      .adaptive_formats
+      .into_iter()
+      .filter(|f| f.mime_type.starts_with("audio/mp4"))
+      .max_by_key(|f| f.bitrate.parse::<u64>().unwrap_or(0))
+      .ok_or(Error::Invidious("no m4a audio stream"))?;
+
+    Ok(Extraction::Proxy {
+      url: self.0.absolute_url(&format.url),
+      headers: vec![],
+    })
+  }
+}
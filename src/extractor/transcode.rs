@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::audio_options::{AudioFormat, AudioOptions};
+use crate::audio_store::{AudioFile, AudioStoreRef};
+use crate::sponsorblock::{self, Segment, SEGMENT_SOURCE};
+use crate::util::start_work;
+use crate::{Error, Result};
+
+use super::{ffmpeg::ffmpeg, Extraction, Extractor, YtdlpFile};
+
+// download the m4a file with yt-dlp, then cut out sponsorblock segments
+// and transcode it with ffmpeg as requested by the audio options.
+// requires both yt-dlp and ffmpeg executables to be in PATH.
+pub struct Transcode {
+  source: YtdlpFile,
+  audio_store: Arc<AudioStoreRef>,
+  options: AudioOptions,
+}
+
+impl Transcode {
+  pub fn new(audio_store: Arc<AudioStoreRef>, options: AudioOptions) -> Self {
+    Self {
+      source: YtdlpFile::new(audio_store.clone()),
+      audio_store,
+      options,
+    }
+  }
+
+  // the segments are only looked up when the cut file isn't stored yet,
+  // so the file behind an audio url never changes once it's served
+  async fn segments(&self, video_id: &str) -> Result<Vec<Segment>> {
+    let Some(categories) = &self.options.sponsorblock else {
+      return Ok(vec![]);
+    };
+
+    // serving the uncut file instead would change the file under
+    // clients that already started playing the cut one
+    SEGMENT_SOURCE
+      .segments(video_id, categories)
+      .await
+      .map_err(|e| {
+        warn!("error getting sponsorblock segments {}: {}", video_id, e);
+        Error::SegmentsUnavailable(e.to_string())
+      })
+  }
+}
+
+#[async_trait]
+impl Extractor for Transcode {
+  async fn extract(&self, video_id: &str) -> Result<Extraction> {
+    let format = self.options.audio_format;
+    let source = self.source.download(video_id).await?;
+
+    if self.options.sponsorblock.is_none() && format == AudioFormat::M4a {
+      let file = source.open().await?;
+      let mime_type = format.mime_type().to_string();
+      return Ok(Extraction::File { file, mime_type });
+    }
+    let audio_id = self.options.audio_id(video_id);
+    let audio_file = self
+      .audio_store
+      .get_or_allocate(audio_id.clone(), format)
+      .await?;
+
+    match audio_file
+      .get_or_download(|| async {
+        let segments = self.segments(video_id).await?;
+        transcode(&source, &audio_file, &segments).await
+      })
+      .await
+    {
+      Ok(file) => Ok(Extraction::File {
+        file,
+        mime_type: format.mime_type().to_string(),
+      }),
+      Err(e) => {
+        warn!("error transcoding audio file {}: {}", audio_id, e);
+
+        // delete errored file
+        drop(audio_file);
+        if let Err(remove_error) =
+          self.audio_store.remove(&audio_id, format).await
+        {
+          warn!("error removing audio file {}: {}", audio_id, remove_error);
+        }
+        Err(e)
+      }
+    }
+  }
+}
+
+async fn transcode(
+  source: &AudioFile,
+  target: &AudioFile,
+  segments: &[Segment],
+) -> Result<()> {
+  // until the file is renamed, so shutdown doesn't delete it
+  let _work = start_work()?;
+  eprintln!(
+    "transcoding audio file: {} -> {} (cutting {} segments)",
+    source.path.display(),
+    target.path.display(),
+    segments.len()
+  );
+
+  // nothing to cut, keep the original file under the cut file's name
+  if segments.is_empty() && source.format == target.format {
+    std::fs::hard_link(&source.path, &target.temp_path)
+      .or_else(|_| std::fs::copy(&source.path, &target.temp_path).map(|_| ()))
+      .map_err(Error::IO)?;
+    std::fs::rename(&target.temp_path, &target.path).map_err(Error::IO)?;
+    return Ok(());
+  }
+
+  let mut args = vec![];
+  if !segments.is_empty() {
+    args.push("-af".to_string());
+    args.push(sponsorblock::ffmpeg_filter(segments));
+  }
+  args.extend(target.format.ffmpeg_args().iter().map(|x| x.to_string()));
+  ffmpeg(&source.path, args, &target.temp_path).await?;
+
+  std::fs::rename(&target.temp_path, &target.path).map_err(Error::IO)?;
+
+  Ok(())
+}
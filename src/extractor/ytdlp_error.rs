@@ -0,0 +1,156 @@
+use std::{sync::LazyLock, time::Duration};
+
+use regex::Regex;
+
+use crate::Error;
+
+// the reasons yt-dlp fails to get a video that we can tell apart
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YtdlpError {
+  #[error("video is private")]
+  Private,
+  #[error("video is for channel members only")]
+  MembersOnly,
+  #[error("video is age-restricted")]
+  AgeRestricted,
+  #[error("video is not available in this region")]
+  GeoBlocked,
+  #[error("video has been removed")]
+  Removed,
+  #[error("live stream has not started yet")]
+  NotStarted(Option<Duration>),
+  #[error("rate limited by youtube")]
+  RateLimited,
+  #[error("network error")]
+  Network,
+}
+
+impl YtdlpError {
+  // whether the video itself can't be served, rather than the backend
+  // failing to get it
+  pub fn is_unavailable(&self) -> bool {
+    use YtdlpError::*;
+    matches!(
+      self,
+      Private
+        | MembersOnly
+        | AgeRestricted
+        | GeoBlocked
+        | Removed
+        | NotStarted(_)
+    )
+  }
+
+  // when the client may try again, if ever
+  pub fn retry_after(&self) -> Option<Duration> {
+    match self {
+      // premieres often say "in a few moments"
+      YtdlpError::NotStarted(starts_in) => {
+        Some(starts_in.unwrap_or(Duration::from_secs(60)))
+      }
+      YtdlpError::RateLimited => Some(Duration::from_secs(600)),
+      YtdlpError::Network => Some(Duration::from_secs(60)),
+      _ => None,
+    }
+  }
+}
+
+// the messages are checked in order, so the bot check ("sign in to
+// confirm you're not a bot") is told apart from the age check
+const PATTERNS: &[(&str, YtdlpError)] = &[
+  ("not a bot", YtdlpError::RateLimited),
+  ("http error 429", YtdlpError::RateLimited),
+  ("too many requests", YtdlpError::RateLimited),
+  // throttling, phrased like a removal ("video unavailable. this
+  // content isn't available, try again later")
+  ("try again later", YtdlpError::RateLimited),
+  ("private video", YtdlpError::Private),
+  ("video is private", YtdlpError::Private),
+  ("members-only", YtdlpError::MembersOnly),
+  ("join this channel", YtdlpError::MembersOnly),
+  ("confirm your age", YtdlpError::AgeRestricted),
+  ("age-restricted", YtdlpError::AgeRestricted),
+  ("inappropriate for some users", YtdlpError::AgeRestricted),
+  ("in your country", YtdlpError::GeoBlocked),
+  ("geo restriction", YtdlpError::GeoBlocked),
+  ("been removed", YtdlpError::Removed),
+  ("been terminated", YtdlpError::Removed),
+  ("no longer available", YtdlpError::Removed),
+  ("video unavailable", YtdlpError::Removed),
+  ("does not exist", YtdlpError::Removed),
+  ("unable to download webpage", YtdlpError::Network),
+  ("unable to download api page", YtdlpError::Network),
+  ("connection reset", YtdlpError::Network),
+  ("connection refused", YtdlpError::Network),
+  ("name resolution", YtdlpError::Network),
+  ("timed out", YtdlpError::Network),
+];
+
+// turn the stderr of a failed yt-dlp run into an error
+pub fn ytdlp_error(stderr: &str) -> Error {
+  classify(stderr)
+    .map(Error::from)
+    .unwrap_or_else(|| Error::AudioStream(stderr.to_string()))
+}
+
+pub fn classify(stderr: &str) -> Option<YtdlpError> {
+  // only look at the errors, warnings mention all sorts of things
+  let errors = stderr
+    .lines()
+    .filter(|line| line.starts_with("ERROR:"))
+    .collect::<Vec<_>>()
+    .join("\n")
+    .to_lowercase();
+
+  if errors.contains("live event will begin") || errors.contains("premieres") {
+    return Some(YtdlpError::NotStarted(starts_in(&errors)));
+  }
+
+  PATTERNS
+    .iter()
+    .find(|(pattern, _)| errors.contains(pattern))
+    .map(|(_, error)| *error)
+}
+
+// e.g. "this live event will begin in 3 hours"
+fn starts_in(message: &str) -> Option<Duration> {
+  static REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"in (\d+) (second|minute|hour|day)s?").unwrap()
+  });
+
+  let captures = REGEX.captures(message)?;
+  let n: u64 = captures[1].parse().ok()?;
+  let unit = match &captures[2] {
+    "second" => 1,
+    "minute" => 60,
+    "hour" => 60 * 60,
+    _ => 24 * 60 * 60,
+  };
+  Some(Duration::from_secs(n * unit))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_classify() {
+    let cases = [
+      ("ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video", Some(YtdlpError::Private)),
+      ("ERROR: [youtube] abc: Join this channel to get access to members-only content like this video, and other exclusive perks.", Some(YtdlpError::MembersOnly)),
+      ("ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.", Some(YtdlpError::AgeRestricted)),
+      ("ERROR: [youtube] abc: Sign in to confirm you’re not a bot. Use --cookies-from-browser or --cookies for the authentication.", Some(YtdlpError::RateLimited)),
+      ("ERROR: [youtube] abc: The uploader has not made this video available in your country", Some(YtdlpError::GeoBlocked)),
+      ("ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader", Some(YtdlpError::Removed)),
+      ("ERROR: [youtube] abc: Video unavailable. This content isn't available, try again later.", Some(YtdlpError::RateLimited)),
+      ("ERROR: [youtube] abc: This live event will begin in 3 hours.", Some(YtdlpError::NotStarted(Some(Duration::from_secs(3 * 3600))))),
+      ("ERROR: [youtube] abc: Premieres in a few moments", Some(YtdlpError::NotStarted(None))),
+      ("ERROR: [youtube] abc: Unable to download webpage: <urlopen error [Errno -3] Temporary failure in name resolution>", Some(YtdlpError::Network)),
+      ("WARNING: [youtube] video unavailable in some formats\nERROR: unknown failure", None),
+    ];
+
+    for (stderr, expected) in cases {
+      assert_eq!(classify(stderr), expected, "{stderr}");
+    }
+  }
+}
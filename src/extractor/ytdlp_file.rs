@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::sync::{Arc, LazyLock};
 
 use async_trait::async_trait;
@@ -6,14 +7,31 @@ use tokio::fs::File;
 use tokio::process::Command;
 use tracing::warn;
 
+use crate::audio_options::AudioFormat;
 use crate::audio_store::{AudioFile, AudioStoreRef};
-use crate::util::YTDLP_PROXY;
-use crate::{Error, Result, YTDLP_MUTEX};
-
-use super::{Extraction, Extractor};
+use crate::util::{acquire_ytdlp, start_work, YTDLP_PROXY};
+use crate::{Error, Result};
+
+use super::{ffmpeg::ffmpeg, ytdlp_error::ytdlp_error, Extraction, Extractor};
+
+// normalize the loudness of downloaded audio to this integrated
+// loudness (in LUFS, e.g. -16) if set.
+static LOUDNORM_TARGET: LazyLock<Option<f32>> = LazyLock::new(|| {
+  let s = std::env::var("LOUDNORM_TARGET").ok()?;
+  let lufs = s
+    .parse::<f32>()
+    .ok()
+    // the range accepted by ffmpeg's loudnorm filter
+    .filter(|lufs| (-70.0..=-5.0).contains(lufs));
+  if lufs.is_none() {
+    warn!("ignoring invalid LOUDNORM_TARGET: {s}");
+  }
+  lufs
+});
 
 // run yt-dlp command line to get audio stream directly.
 // requires yt-dlp executable to be in PATH.
+#[derive(Clone)]
 pub struct YtdlpFile {
   audio_store: Arc<AudioStoreRef>,
 }
@@ -22,37 +40,79 @@ impl YtdlpFile {
   pub fn new(audio_store: Arc<AudioStoreRef>) -> Self {
     Self { audio_store }
   }
-}
 
-#[async_trait]
-impl Extractor for YtdlpFile {
-  async fn extract(&self, video_id: &str) -> Result<Extraction> {
-    let audio_file = self
-      .audio_store
-      .get_or_allocate(video_id.to_string())
-      .await?;
+  // download the m4a file into the audio store unless it's already there
+  pub async fn download(&self, video_id: &str) -> Result<Arc<AudioFile>> {
+    let audio_file = self.allocate(video_id).await?;
+    self.finish_download(&audio_file).await?;
+    Ok(audio_file)
+  }
 
-    match audio_file
-      .get_or_download(|| async { download_file(&audio_file).await })
+  async fn allocate(&self, video_id: &str) -> Result<Arc<AudioFile>> {
+    self
+      .audio_store
+      .get_or_allocate(video_id.to_string(), AudioFormat::M4a)
       .await
-    {
-      Ok(file) => serve_file(file).await,
+  }
+
+  async fn finish_download(&self, audio_file: &AudioFile) -> Result<()> {
+    match audio_file.download(|| download_file(audio_file)).await {
+      Ok(()) => Ok(()),
       Err(e) => {
-        warn!("error getting audio file {}: {}", video_id, e);
+        warn!("error getting audio file {}: {}", audio_file.id, e);
 
         // delete errored file
-        drop(audio_file);
-        self.audio_store.remove(video_id).await.unwrap();
+        let removed = self
+          .audio_store
+          .remove(&audio_file.id, AudioFormat::M4a)
+          .await;
+        if let Err(remove_error) = removed {
+          warn!(
+            "error removing audio file {}: {}",
+            audio_file.id, remove_error
+          );
+        }
         Err(e)
       }
     }
   }
 }
 
+#[async_trait]
+impl Extractor for YtdlpFile {
+  async fn extract(&self, video_id: &str) -> Result<Extraction> {
+    // loudness normalization rewrites the whole file after download,
+    // so it can only be served once complete
+    if LOUDNORM_TARGET.is_some() {
+      let audio_file = self.download(video_id).await?;
+      return serve_file(audio_file.open().await?).await;
+    }
+
+    let audio_file = self.allocate(video_id).await?;
+    if audio_file.is_ready() {
+      return serve_file(audio_file.open().await?).await;
+    }
+
+    // keep downloading even if the client goes away
+    let downloader = self.clone();
+    let file = audio_file.clone();
+    tokio::spawn(async move { downloader.finish_download(&file).await });
+
+    audio_file.wait_until_started().await?;
+    let mime_type = AudioFormat::M4a.mime_type().to_string();
+    Ok(Extraction::GrowingFile {
+      file: audio_file,
+      mime_type,
+    })
+  }
+}
+
 async fn download_file(audio_file: &AudioFile) -> Result<()> {
   let audio_id = &audio_file.id;
   let temp_path = &audio_file.temp_path;
   let url = format!("https://youtube.com/watch?v={audio_id}");
+  // until the file is renamed, so shutdown doesn't delete it
+  let _work = start_work()?;
   eprintln!("downloading audio file: {}", url);
 
   let mut cmd = Command::new("yt-dlp");
@@ -61,10 +121,18 @@ async fn download_file(audio_file: &AudioFile) -> Result<()> {
     .arg("-f")
     .arg("ba[ext=m4a]")
     .arg("--no-progress")
+    // the file is served while it's being written, so write it in place
+    // and keep the container as downloaded. rewriting it afterwards would
+    // move the bytes under the clients' range offsets.
+    .arg("--no-part")
+    .arg("--fixup")
+    .arg("never")
     .arg("-o")
     .arg(temp_path)
     .arg("--no-mtime")
-    .arg(url);
+    .arg(url)
+    // don't outlive the server on shutdown
+    .kill_on_drop(true);
 
   if let Some(proxy) = &*YTDLP_PROXY {
     // used to remove cred info from proxy url before printing
@@ -77,26 +145,46 @@ async fn download_file(audio_file: &AudioFile) -> Result<()> {
     cmd.arg("--proxy").arg(proxy);
   }
 
-  let guard = YTDLP_MUTEX.acquire().await.unwrap();
+  let guard = acquire_ytdlp(format!("download {audio_id}")).await?;
   let child = cmd.stderr(std::process::Stdio::piped()).spawn()?;
   let output = child.wait_with_output().await?;
   drop(guard);
   detect_error(&output.stderr)?;
 
+  // only done when the file isn't served while it grows
+  if let Some(lufs) = *LOUDNORM_TARGET {
+    normalize_loudness(temp_path, lufs).await?;
+  }
+
   std::fs::rename(temp_path, &audio_file.path).map_err(Error::IO)?;
 
   Ok(())
 }
 
+// apply EBU R128 loudness normalization to the file in place
+async fn normalize_loudness(path: &Path, lufs: f32) -> Result<()> {
+  eprintln!("normalizing loudness to {} LUFS: {}", lufs, path.display());
+
+  let normalized_path = path.with_extension("loudnorm.m4a");
+  let filter = format!("loudnorm=I={lufs}:TP=-1.5:LRA=11");
+  let args = ["-af", &filter]
+    .into_iter()
+    .chain(AudioFormat::M4a.ffmpeg_args().iter().copied());
+  ffmpeg(path, args, &normalized_path).await?;
+
+  std::fs::rename(&normalized_path, path).map_err(Error::IO)?;
+  Ok(())
+}
+
 async fn serve_file(file: File) -> Result<Extraction> {
-  let mime_type = "audio/mp4".to_string();
+  let mime_type = AudioFormat::M4a.mime_type().to_string();
   Ok(Extraction::File { file, mime_type })
 }
 
 fn detect_error(bytes: &[u8]) -> Result<()> {
   let s = String::from_utf8_lossy(bytes);
   if s.contains("ERROR:") {
-    Err(Error::AudioStream(s.to_string()))
+    Err(ytdlp_error(&s))
   } else {
     Ok(())
   }
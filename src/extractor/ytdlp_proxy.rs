@@ -2,13 +2,12 @@ use std::collections::HashMap;
 
 use async_trait::async_trait;
 
-use crate::{Error, Result, YTDLP_MUTEX};
+use crate::{util::acquire_ytdlp, Error, Result};
 
 use super::{Extraction, Extractor};
 
 // run yt-dlp command line to get audio playback url.
 // requires yt-dlp executable to be in PATH.
-#[allow(unused)]
 pub struct YtdlpProxy;
 
 #[async_trait]
@@ -34,7 +33,7 @@ impl Extractor for YtdlpProxy {
     }
 
     let url = format!("https://youtube.com/watch?v={video_id}");
-    let guard = YTDLP_MUTEX.acquire().await;
+    let guard = acquire_ytdlp(format!("proxy {video_id}")).await?;
     let output = Command::new("yt-dlp").arg("-j").arg(url).output().await?;
     drop(guard);
     let output =
@@ -5,9 +5,12 @@ use tokio::io::{AsyncWriteExt as _, BufReader};
 use tokio::process::Command;
 use tokio_util::io::ReaderStream;
 
-use crate::{Error, Result, YTDLP_MUTEX};
+use crate::{util::acquire_ytdlp, Error, Result};
 
-use super::{Extraction, Extractor};
+use super::{
+  ytdlp_error::{classify, ytdlp_error},
+  Extraction, Extractor,
+};
 
 // run yt-dlp command line to get audio stream directly.
 // requires yt-dlp executable to be in PATH.
@@ -19,14 +22,14 @@ impl Extractor for YtdlpStream {
     let url = format!("https://youtube.com/watch?v={video_id}");
 
     let info_json = get_info_json(&url).await?;
-    stream(&info_json).await
+    stream(video_id, &info_json).await
   }
 }
 
 fn detect_error(bytes: Bytes) -> Result<Bytes> {
   let s = String::from_utf8_lossy(&bytes);
   if s.contains("ERROR:") {
-    Err(Error::AudioStream(s.to_string()))
+    Err(ytdlp_error(&s))
   } else {
     // do not actually output anything
     Ok(Bytes::new())
@@ -49,6 +52,9 @@ async fn get_info_json(url: &str) -> Result<String> {
 
   if !output.status.success() {
     let stderr = String::from_utf8_lossy(&output.stderr);
+    if let Some(e) = classify(&stderr) {
+      return Err(e.into());
+    }
     let message = format!(
       "yt-dlp exited with code ({:?}): {}",
       output.status.code(),
@@ -61,10 +67,10 @@ async fn get_info_json(url: &str) -> Result<String> {
   Ok(stdout.to_string())
 }
 
-async fn stream(info_json: &str) -> Result<Extraction> {
+async fn stream(video_id: &str, info_json: &str) -> Result<Extraction> {
   let info: InfoJson = serde_json::from_str(info_json)?;
 
-  let guard = YTDLP_MUTEX.acquire().await;
+  let guard = acquire_ytdlp(format!("stream {video_id}")).await?;
   let mut child = Command::new("yt-dlp")
     .arg("--load-info-json")
     .arg("-")
@@ -75,6 +81,7 @@ async fn stream(info_json: &str) -> Result<Extraction> {
     .stdin(std::process::Stdio::piped())
     .stdout(std::process::Stdio::piped())
     .stderr(std::process::Stdio::piped())
+    .kill_on_drop(true)
     .spawn()?;
 
   let mut stdin = child.stdin.take().expect("stdin not opened");
@@ -1,25 +1,114 @@
+use std::time::SystemTime;
+
 use axum::{
   body::{self, Bytes},
   extract::{Path, Query},
-  headers::ContentType,
+  headers::{
+    ContentType, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified,
+  },
   http::Response,
   response::IntoResponse,
   TypedHeader,
 };
-use futures::future::select_ok;
+use chrono::DateTime;
+use futures::{
+  future::{join_all, select_ok, BoxFuture},
+  FutureExt,
+};
 use http_types::Url;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::{header, StatusCode};
+use serde::Deserialize;
 
 use crate::{
-  harvestor, harvestor::Harvestor, piped::PipedInstance, Error, Result,
-  INSTANCE_PUBLIC_URL,
+  audio_options::AudioOptions,
+  channel_filter,
+  config::CONFIG,
+  feed_cache, harvestor,
+  harvestor::Harvestor,
+  health::{BackendId, HEALTH},
+  invidious::InvidiousInstance,
+  piped::PipedInstance,
+  podcast::{JsonFeed, Podcast},
+  prefetch,
+  rate_limit::FEED_RATE_LIMIT,
+  subscriber::Access,
+  util::with_timeout,
+  Error, Result, INSTANCE_PUBLIC_URL,
 };
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+  #[default]
+  Rss,
+  Atom,
+  Json,
+}
+
+impl FeedFormat {
+  fn content_type(self) -> &'static str {
+    match self {
+      FeedFormat::Rss => "application/rss+xml; charset=UTF-8",
+      FeedFormat::Atom => "application/atom+xml; charset=UTF-8",
+      FeedFormat::Json => "application/feed+json; charset=UTF-8",
+    }
+  }
+
+  // the first media range in the accept header that names a feed format
+  fn from_accept(accept: &str) -> Option<Self> {
+    accept.split(',').find_map(|range| {
+      match range.split(';').next().unwrap_or("").trim() {
+        "application/rss+xml" => Some(FeedFormat::Rss),
+        "application/atom+xml" => Some(FeedFormat::Atom),
+        "application/feed+json" | "application/json" => Some(FeedFormat::Json),
+        _ => None,
+      }
+    })
+  }
+
+  // an explicit ?format= takes precedence over the accept header
+  fn negotiate(query: &FeedQuery, headers: &header::HeaderMap) -> Self {
+    query
+      .format
+      .or_else(|| {
+        headers
+          .get(header::ACCEPT)
+          .and_then(|v| v.to_str().ok())
+          .and_then(Self::from_accept)
+      })
+      .unwrap_or_default()
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+  format: Option<FeedFormat>,
+}
+
+// upper bound of channels and playlists in a single bundle
+const MAX_BUNDLE_SOURCES: usize = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct BundleQuery {
+  // comma-separated channel ids
+  #[serde(default)]
+  channels: String,
+  // comma-separated playlist ids
+  #[serde(default)]
+  playlists: String,
+  title: Option<String>,
+  image: Option<String>,
+}
+
 pub async fn channel_podcast_xml(
   Path(channel_id): Path<String>,
+  Query(audio_options): Query<AudioOptions>,
+  Query(feed_query): Query<FeedQuery>,
   piped: Option<PipedInstance>,
+  invidious: InvidiousInstance,
+  access: Access,
   req_headers: header::HeaderMap,
 ) -> Result<impl IntoResponse> {
   let user_agent = req_headers
@@ -32,24 +121,285 @@ pub async fn channel_podcast_xml(
     channel_id, user_agent
   );
 
-  let mut harvestors: Vec<Box<dyn Harvestor + Send>> = vec![];
-  harvestors.push(Box::new(harvestor::Ytdlp::new()));
-  if let Some(piped) = piped {
-    harvestors.push(Box::new(harvestor::RssPiped::new(piped)));
+  channel_filter::check_channel(&channel_id, None).await?;
+  if !feed_cache::contains(&channel_cache_key(&channel_id)) {
+    FEED_RATE_LIMIT.check(access.client(), 1)?;
+  }
+
+  prefetch::touch_channel(&channel_id);
+  let mut podcast = cached_channel(channel_id, piped, invidious).await?;
+  podcast.set_audio_options(&audio_options);
+  podcast.set_access(&access);
+
+  let format = FeedFormat::negotiate(&feed_query, &req_headers);
+  podcast_response(podcast, format, &req_headers)
+}
+
+pub async fn playlist_podcast_xml(
+  Path(playlist_id): Path<String>,
+  Query(audio_options): Query<AudioOptions>,
+  Query(feed_query): Query<FeedQuery>,
+  access: Access,
+  req_headers: header::HeaderMap,
+) -> Result<impl IntoResponse> {
+  let user_agent = req_headers
+    .get(header::USER_AGENT)
+    .and_then(|v| v.to_str().ok())
+    .unwrap_or("unknown");
+
+  eprintln!(
+    "client requesting playlist podcast: {} (user-agent: {})",
+    playlist_id, user_agent
+  );
+
+  channel_filter::check_playlist(&playlist_id).await?;
+  if !feed_cache::contains(&playlist_cache_key(&playlist_id)) {
+    FEED_RATE_LIMIT.check(access.client(), 1)?;
+  }
+
+  let mut podcast = cached_playlist(playlist_id).await?;
+  podcast.set_audio_options(&audio_options);
+  podcast.set_access(&access);
+
+  let format = FeedFormat::negotiate(&feed_query, &req_headers);
+  podcast_response(podcast, format, &req_headers)
+}
+
+pub async fn bundle_podcast_xml(
+  Query(bundle): Query<BundleQuery>,
+  Query(audio_options): Query<AudioOptions>,
+  Query(feed_query): Query<FeedQuery>,
+  piped: Option<PipedInstance>,
+  invidious: InvidiousInstance,
+  access: Access,
+  req_headers: header::HeaderMap,
+) -> Result<impl IntoResponse> {
+  let split_ids = |ids: &str| -> Vec<String> {
+    ids
+      .split(',')
+      .map(str::trim)
+      .filter(|id| !id.is_empty())
+      .map(str::to_string)
+      .collect()
+  };
+  let channel_ids = split_ids(&bundle.channels);
+  let playlist_ids = split_ids(&bundle.playlists);
+
+  match channel_ids.len() + playlist_ids.len() {
+    0 => return Err(Error::InvalidBundle("no channels or playlists given")),
+    n if n > MAX_BUNDLE_SOURCES => {
+      return Err(Error::InvalidBundle("too many channels or playlists"))
+    }
+    _ => (),
+  }
+
+  eprintln!(
+    "client requesting bundle podcast: channels {:?}, playlists {:?}",
+    channel_ids, playlist_ids
+  );
+
+  for channel_id in &channel_ids {
+    channel_filter::check_channel(channel_id, None).await?;
+  }
+  for playlist_id in &playlist_ids {
+    channel_filter::check_playlist(playlist_id).await?;
+  }
+
+  let bundle_url = bundle_url(&channel_ids, &playlist_ids);
+
+  // every source that needs a harvest counts
+  let harvests = channel_ids
+    .iter()
+    .map(|id| channel_cache_key(id))
+    .chain(playlist_ids.iter().map(|id| playlist_cache_key(id)))
+    .filter(|key| !feed_cache::contains(key))
+    .count();
+  FEED_RATE_LIMIT.check(access.client(), harvests as u32)?;
+
+  let channels = channel_ids
+    .into_iter()
+    .map(|id| cached_channel(id, piped.clone(), invidious.clone()).boxed());
+  let playlists = playlist_ids
+    .into_iter()
+    .map(|id| cached_playlist(id).boxed());
+  let harvests: Vec<BoxFuture<'_, Result<Podcast>>> =
+    channels.chain(playlists).collect();
+
+  let mut podcasts = vec![];
+  let mut last_error = None;
+  for result in join_all(harvests).await {
+    match result {
+      Ok(podcast) => podcasts.push(podcast),
+      Err(e) => {
+        eprintln!("failed to harvest bundle source: {e}");
+        last_error = Some(e);
+      }
+    }
+  }
+
+  // a single broken source shouldn't take the whole bundle down
+  if podcasts.is_empty() {
+    return Err(last_error.expect("bundle has at least one source"));
+  }
+
+  let mut podcast =
+    Podcast::bundle(podcasts, bundle_url, bundle.title, bundle.image);
+  podcast.set_audio_options(&audio_options);
+  podcast.set_access(&access);
+
+  let format = FeedFormat::negotiate(&feed_query, &req_headers);
+  podcast_response(podcast, format, &req_headers)
+}
+
+// the bundle of these sources on this instance
+fn bundle_url(channel_ids: &[String], playlist_ids: &[String]) -> String {
+  let mut url = format!("{}/bundle", &*INSTANCE_PUBLIC_URL);
+  let mut query = vec![];
+  if !channel_ids.is_empty() {
+    query.push(format!("channels={}", channel_ids.join(",")));
   }
+  if !playlist_ids.is_empty() {
+    query.push(format!("playlists={}", playlist_ids.join(",")));
+  }
+  if !query.is_empty() {
+    url.push('?');
+    url.push_str(&query.join("&"));
+  }
+  url
+}
 
-  let (podcast, _) =
-    select_ok(harvestors.iter().map(|h| h.harvest(&channel_id))).await?;
+fn channel_cache_key(channel_id: &str) -> String {
+  format!("channel/{channel_id}")
+}
 
-  let podcast_channel: rss::Channel = podcast.into();
+async fn cached_channel(
+  channel_id: String,
+  piped: Option<PipedInstance>,
+  invidious: InvidiousInstance,
+) -> Result<Podcast> {
+  let key = channel_cache_key(&channel_id);
+  feed_cache::get_or_harvest(key, || async move {
+    harvest_channel(&channel_id, piped, invidious).await
+  })
+  .await
+}
+
+// harvest the channel regardless of the cache and store the result
+pub async fn refresh_channel(channel_id: &str) -> Result<Podcast> {
+  let podcast =
+    harvest_channel(channel_id, None, InvidiousInstance::default()).await?;
+  feed_cache::insert(channel_cache_key(channel_id), podcast.clone());
+  Ok(podcast)
+}
+
+fn playlist_cache_key(playlist_id: &str) -> String {
+  format!("playlist/{playlist_id}")
+}
+
+async fn cached_playlist(playlist_id: String) -> Result<Podcast> {
+  let key = playlist_cache_key(&playlist_id);
+  let mut podcast = feed_cache::get_or_harvest(key, || async move {
+    let podcast = harvestor::YtdlpPlaylist::new()
+      .harvest(&playlist_id)
+      .await?;
+    channel_filter::record_playlist(&playlist_id, &podcast);
+    Ok(podcast)
+  })
+  .await?;
+  // after the cache, as the filter can change
+  channel_filter::remove_denied(&mut podcast).await;
+  Ok(podcast)
+}
+
+// harvest a channel with the first successful configured harvestor
+async fn harvest_channel(
+  channel_id: &str,
+  piped: Option<PipedInstance>,
+  invidious: InvidiousInstance,
+) -> Result<Podcast> {
+  let configs = CONFIG.harvestors.iter().collect();
+  let configs =
+    HEALTH.select(configs, |c| BackendId::harvestor(c.backend.name()));
+  let harvests = configs.into_iter().filter_map(|config| {
+    let harvestor =
+      harvestor::build(&config.backend, piped.as_ref(), &invidious)?;
+    let name = config.backend.name();
+    let harvest = async move {
+      let harvest = harvestor.harvest(channel_id);
+      let harvest = with_timeout(config.timeout(), name, harvest);
+      HEALTH.track(BackendId::harvestor(name), harvest).await
+    };
+    Some(harvest.boxed())
+  });
+  let harvests: Vec<_> = harvests.collect();
+  if harvests.is_empty() {
+    return Err(Error::Config("no usable harvestor configured".into()));
+  }
+
+  let (podcast, _) = select_ok(harvests).await?;
+  channel_filter::record_channel(channel_id, &podcast);
+
+  Ok(podcast)
+}
+
+fn podcast_response(
+  podcast: Podcast,
+  format: FeedFormat,
+  req_headers: &header::HeaderMap,
+) -> Result<Response<body::Full<Bytes>>> {
+  let last_modified = DateTime::parse_from_rfc2822(&podcast.last_build_date)
+    .ok()
+    .map(SystemTime::from);
 
   let mut output = Vec::new();
-  podcast_channel.pretty_write_to(&mut output, b' ', 2)?;
+  match format {
+    FeedFormat::Rss => {
+      let podcast_channel: rss::Channel = podcast.into();
+      podcast_channel.pretty_write_to(&mut output, b' ', 2)?;
+    }
+    FeedFormat::Atom => {
+      let podcast_feed: atom_syndication::Feed = podcast.into();
+      podcast_feed.write_to(&mut output)?;
+    }
+    FeedFormat::Json => {
+      let podcast_feed: JsonFeed = podcast.into();
+      serde_json::to_writer_pretty(&mut output, &podcast_feed)?;
+    }
+  }
+
+  let etag: ETag = feed_cache::etag(&output)
+    .parse()
+    .expect("etag is a quoted base64 string");
+
+  // if-none-match takes precedence over if-modified-since (rfc 9110)
+  let not_modified = match req_headers.typed_get::<IfNoneMatch>() {
+    Some(if_none_match) => !if_none_match.precondition_passes(&etag),
+    None => match (req_headers.typed_get::<IfModifiedSince>(), last_modified) {
+      (Some(since), Some(modified)) => !since.is_modified(modified),
+      _ => false,
+    },
+  };
+
+  let mut resp = if not_modified {
+    Response::builder()
+      .status(StatusCode::NOT_MODIFIED)
+      .body(body::Full::default())?
+  } else {
+    Response::builder()
+      .status(StatusCode::OK)
+      .header(header::CONTENT_TYPE, format.content_type())
+      .body(body::Full::new(Bytes::from(output)))?
+  };
 
-  let resp = Response::builder()
-    .status(StatusCode::OK)
-    .header(header::CONTENT_TYPE, "application/rss+xml; charset=UTF-8")
-    .body(body::Full::new(Bytes::from(output)))?;
+  resp.headers_mut().typed_insert(etag);
+  // the format is picked by the accept header unless asked for
+  let vary = header::HeaderValue::from_static("accept");
+  resp.headers_mut().insert(header::VARY, vary);
+  if let Some(modified) = last_modified {
+    resp
+      .headers_mut()
+      .typed_insert(LastModified::from(modified));
+  }
 
   Ok(resp)
 }
@@ -61,20 +411,40 @@ pub struct GetPodcastReq {
 
 pub async fn channel_podcast_url(
   Query(req): Query<GetPodcastReq>,
+  access: Access,
 ) -> Result<impl IntoResponse> {
-  let channel_id = find_youtube_channel_id(&req.url).await?;
-  let podcast_url = format!("{}/channel/{channel_id}", &*INSTANCE_PUBLIC_URL);
+  let mut podcast_url = match extract_youtube_channel_ref(&req.url)? {
+    ChannelRef::Playlist(playlist_id) => {
+      channel_filter::check_playlist(&playlist_id).await?;
+      format!("{}/playlist/{playlist_id}", &*INSTANCE_PUBLIC_URL)
+    }
+    channel_ref => {
+      let handle = match &channel_ref {
+        ChannelRef::Handle(handle) => Some(handle.clone()),
+        _ => None,
+      };
+      let channel_id = find_youtube_channel_id_from_ref(channel_ref).await?;
+      channel_filter::check_channel(&channel_id, handle.as_deref()).await?;
+      format!("{}/channel/{channel_id}", &*INSTANCE_PUBLIC_URL)
+    }
+  };
+  if let Some((name, token)) = access.token_query() {
+    podcast_url = format!("{podcast_url}?{name}={token}");
+  }
   let content_type = TypedHeader(ContentType::text());
 
   Ok((content_type, podcast_url))
 }
 
-async fn find_youtube_channel_id(url: &str) -> Result<String> {
-  let channel_ref = extract_youtube_channel_ref(url)?;
-  find_youtube_channel_id_from_ref(channel_ref).await
+// the channel id of a handle, with or without the leading @
+pub async fn resolve_handle(handle: &str) -> Result<String> {
+  let handle = handle.trim_start_matches('@').to_string();
+  find_youtube_channel_id_from_ref(ChannelRef::Handle(handle)).await
 }
 
-async fn find_youtube_channel_id_from_ref(channel_ref: ChannelRef) -> Result<String> {
+async fn find_youtube_channel_id_from_ref(
+  channel_ref: ChannelRef,
+) -> Result<String> {
   let url = match channel_ref {
     ChannelRef::Name(name) => {
       format!("https://www.youtube.com/c/{name}")
@@ -83,6 +453,9 @@ async fn find_youtube_channel_id_from_ref(channel_ref: ChannelRef) -> Result<Str
       format!("https://www.youtube.com/@{handle}")
     }
     ChannelRef::Id(id) => return Ok(id),
+    ChannelRef::Playlist(id) => {
+      return Err(Error::UnsupportedURL(id, "playlist is not a channel"))
+    }
   };
 
   let resp = reqwest::get(url).await?;
@@ -114,6 +487,7 @@ async fn find_youtube_channel_id_from_ref(channel_ref: ChannelRef) -> Result<Str
   Ok(channel_id.to_string())
 }
 
+#[derive(Debug, PartialEq)]
 enum ChannelRef {
   // https://www.youtube.com/channel/UCZYTClx2T1of7BRZ86-8fow
   Id(String),
@@ -121,6 +495,8 @@ enum ChannelRef {
   Name(String),
   // https://www.youtube.com/@ComplexityExplorer
   Handle(String),
+  // https://www.youtube.com/playlist?list=PLFs4vir_WsTwEd-nJgVJCZPNL3HALHHpF
+  Playlist(String),
 }
 
 fn extract_youtube_channel_ref(url: &str) -> Result<ChannelRef> {
@@ -130,6 +506,8 @@ fn extract_youtube_channel_ref(url: &str) -> Result<ChannelRef> {
     Lazy::new(|| regex::Regex::new(r"^c/([a-zA-Z0-9_]+)").unwrap());
   static HANDLE_REGEX: Lazy<Regex> =
     Lazy::new(|| regex::Regex::new(r"^@([a-zA-Z0-9_]+)$").unwrap());
+  static PLAYLIST_ID_REGEX: Lazy<Regex> =
+    Lazy::new(|| regex::Regex::new(r"^[a-zA-Z0-9_-]+$").unwrap());
 
   let url: Url = url.parse()?;
 
@@ -155,5 +533,103 @@ fn extract_youtube_channel_ref(url: &str) -> Result<ChannelRef> {
     return Ok(ChannelRef::Handle(handle));
   }
 
+  if path == "playlist" {
+    let list = url
+      .query_pairs()
+      .find_map(|(k, v)| (k == "list").then_some(v))
+      .filter(|list| PLAYLIST_ID_REGEX.is_match(list));
+
+    if let Some(list) = list {
+      return Ok(ChannelRef::Playlist(list.into_owned()));
+    }
+  }
+
   Err(Error::UnsupportedURL(url.into(), "invalid youtube url"))
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_extract_youtube_channel_ref() {
+    let extract = |url| extract_youtube_channel_ref(url).ok();
+
+    assert_eq!(
+      extract("https://www.youtube.com/@ComplexityExplorer"),
+      Some(ChannelRef::Handle("ComplexityExplorer".into()))
+    );
+    assert_eq!(
+      extract("https://www.youtube.com/playlist?list=PLFs4vir_WsTw-nJgVJ"),
+      Some(ChannelRef::Playlist("PLFs4vir_WsTw-nJgVJ".into()))
+    );
+    assert_eq!(
+      extract("https://m.youtube.com/playlist?si=abc&list=PL123"),
+      Some(ChannelRef::Playlist("PL123".into()))
+    );
+    assert_eq!(extract("https://www.youtube.com/playlist"), None);
+    assert_eq!(extract("https://www.youtube.com/playlist?list=a/b"), None);
+  }
+
+  #[test]
+  fn test_feed_format_from_accept() {
+    let from_accept = FeedFormat::from_accept;
+
+    assert_eq!(from_accept("application/atom+xml"), Some(FeedFormat::Atom));
+    assert_eq!(
+      from_accept("text/html, application/feed+json;q=0.9"),
+      Some(FeedFormat::Json)
+    );
+    assert_eq!(
+      from_accept("application/rss+xml, application/atom+xml;q=0.8"),
+      Some(FeedFormat::Rss)
+    );
+    assert_eq!(from_accept("*/*"), None);
+  }
+
+  #[test]
+  fn test_bundle_url() {
+    let ids = |ids: &[&str]| -> Vec<String> {
+      ids.iter().map(|id| id.to_string()).collect()
+    };
+    let url = bundle_url(&ids(&["UCa", "UCb"]), &ids(&["PLa"]));
+    assert!(url.ends_with("/bundle?channels=UCa,UCb&playlists=PLa"));
+    let url = bundle_url(&[], &ids(&["PLa"]));
+    assert!(url.ends_with("/bundle?playlists=PLa"));
+  }
+
+  #[test]
+  fn test_podcast_response_not_modified() {
+    let podcast = Podcast {
+      title: "Podcast".into(),
+      last_build_date: "Tue, 01 Aug 2023 10:00:00 +0000".into(),
+      ..Default::default()
+    };
+    let respond = |headers: &[(&str, &str)]| {
+      let mut req_headers = header::HeaderMap::new();
+      for (name, value) in headers {
+        let name = header::HeaderName::from_bytes(name.as_bytes()).unwrap();
+        req_headers.insert(name, value.parse().unwrap());
+      }
+      podcast_response(podcast.clone(), FeedFormat::Rss, &req_headers).unwrap()
+    };
+
+    let resp = respond(&[]);
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()[header::VARY], "accept");
+    let etag = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+
+    let resp = respond(&[("if-none-match", &etag)]);
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(resp.headers()[header::ETAG], etag.as_str());
+
+    let since = "Wed, 02 Aug 2023 10:00:00 GMT";
+    let resp = respond(&[("if-modified-since", since)]);
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+    // if-none-match wins over if-modified-since
+    let resp =
+      respond(&[("if-none-match", "\"other\""), ("if-modified-since", since)]);
+    assert_eq!(resp.status(), StatusCode::OK);
+  }
+}
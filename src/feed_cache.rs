@@ -0,0 +1,209 @@
+use std::{
+  collections::HashMap,
+  future::Future,
+  sync::{Arc, LazyLock, Mutex},
+  time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use lru_time_cache::LruCache;
+use ring::digest;
+
+use crate::{podcast::Podcast, Result};
+
+// how long a harvested podcast is served without asking upstream again
+static FEED_CACHE_TTL: LazyLock<Duration> =
+  LazyLock::new(|| duration_from_env("FEED_CACHE_TTL", 30 * 60));
+
+// how long past the ttl a podcast may still be served while it's being
+// refreshed in the background
+static FEED_CACHE_MAX_STALE: LazyLock<Duration> =
+  LazyLock::new(|| duration_from_env("FEED_CACHE_MAX_STALE", 24 * 60 * 60));
+
+static FEED_CACHE: LazyLock<Mutex<LruCache<String, CachedPodcast>>> =
+  LazyLock::new(|| {
+    Mutex::new(LruCache::with_expiry_duration_and_capacity(
+      *FEED_CACHE_TTL + *FEED_CACHE_MAX_STALE,
+      500,
+    ))
+  });
+
+// a lock per key being harvested, so concurrent misses wait for the
+// first one's harvest instead of starting their own
+type Harvesting = HashMap<String, Arc<tokio::sync::Mutex<()>>>;
+
+static HARVESTING: LazyLock<Mutex<Harvesting>> =
+  LazyLock::new(Default::default);
+
+struct CachedPodcast {
+  podcast: Podcast,
+  harvested_at: Instant,
+  refreshing: bool,
+}
+
+fn duration_from_env(name: &str, default_secs: u64) -> Duration {
+  let secs = std::env::var(name)
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(default_secs);
+  Duration::from_secs(secs)
+}
+
+// return the cached podcast for the key, harvesting it on a miss.
+// a stale podcast is returned as is while a refresh runs in the
+// background.
+pub async fn get_or_harvest<F, Fut>(key: String, harvest: F) -> Result<Podcast>
+where
+  F: FnOnce() -> Fut,
+  Fut: Future<Output = Result<Podcast>> + Send + 'static,
+{
+  let stale = {
+    let mut cache = FEED_CACHE.lock().unwrap();
+    match cache.get_mut(&key) {
+      Some(entry) if entry.harvested_at.elapsed() < *FEED_CACHE_TTL => {
+        return Ok(entry.podcast.clone());
+      }
+      Some(entry)
+        if entry.harvested_at.elapsed()
+          < *FEED_CACHE_TTL + *FEED_CACHE_MAX_STALE =>
+      {
+        let needs_refresh = !entry.refreshing;
+        entry.refreshing = true;
+        Some((entry.podcast.clone(), needs_refresh))
+      }
+      _ => None,
+    }
+  };
+
+  match stale {
+    Some((podcast, needs_refresh)) => {
+      if needs_refresh {
+        tokio::spawn(refresh(key, harvest()));
+      }
+      Ok(podcast)
+    }
+    None => harvest_once(key, harvest).await,
+  }
+}
+
+async fn harvest_once<F, Fut>(key: String, harvest: F) -> Result<Podcast>
+where
+  F: FnOnce() -> Fut,
+  Fut: Future<Output = Result<Podcast>>,
+{
+  let lock = HARVESTING
+    .lock()
+    .unwrap()
+    .entry(key.clone())
+    .or_default()
+    .clone();
+
+  let guard = lock.lock().await;
+  // harvested while we were waiting
+  let cached = FEED_CACHE
+    .lock()
+    .unwrap()
+    .peek(&key)
+    .map(|e| e.podcast.clone());
+  let result = match cached {
+    Some(podcast) => Ok(podcast),
+    None => harvest().await.inspect(|podcast| {
+      insert(key.clone(), podcast.clone());
+    }),
+  };
+  drop(guard);
+
+  // the last one out cleans up
+  let mut harvesting = HARVESTING.lock().unwrap();
+  if Arc::strong_count(&lock) == 2 {
+    harvesting.remove(&key);
+  }
+  result
+}
+
+async fn refresh(key: String, harvest: impl Future<Output = Result<Podcast>>) {
+  match harvest.await {
+    Ok(podcast) => insert(key, podcast),
+    Err(e) => {
+      eprintln!("failed to refresh cached feed {key}: {e}");
+      if let Some(entry) = FEED_CACHE.lock().unwrap().get_mut(&key) {
+        entry.refreshing = false;
+      }
+    }
+  }
+}
+
+// whether the podcast can be served without harvesting it first
+pub fn contains(key: &str) -> bool {
+  FEED_CACHE.lock().unwrap().peek(key).is_some()
+}
+
+pub fn insert(key: String, podcast: Podcast) {
+  let entry = CachedPodcast {
+    podcast,
+    harvested_at: Instant::now(),
+    refreshing: false,
+  };
+  FEED_CACHE.lock().unwrap().insert(key, entry);
+}
+
+// a strong etag of a rendered feed, the same across releases
+pub fn etag(body: &[u8]) -> String {
+  let digest = digest::digest(&digest::SHA256, body);
+  format!("\"{}\"", URL_SAFE_NO_PAD.encode(digest))
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_get_or_harvest() {
+    fn podcast(title: &str) -> Podcast {
+      Podcast {
+        title: title.to_string(),
+        ..Default::default()
+      }
+    }
+    let key = "test/get_or_harvest".to_string();
+
+    let first = get_or_harvest(key.clone(), || async { Ok(podcast("first")) })
+      .await
+      .unwrap();
+    let second =
+      get_or_harvest(key.clone(), || async { Ok(podcast("second")) })
+        .await
+        .unwrap();
+
+    assert_eq!(first.title, "first");
+    assert_eq!(second.title, "first");
+  }
+
+  #[tokio::test]
+  async fn test_concurrent_misses_harvest_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static HARVESTS: AtomicUsize = AtomicUsize::new(0);
+    let key = "test/concurrent_misses".to_string();
+    let get = || {
+      get_or_harvest(key.clone(), || async {
+        HARVESTS.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(Podcast::default())
+      })
+    };
+
+    let (a, b) = tokio::join!(get(), get());
+    assert!(a.is_ok() && b.is_ok());
+    assert_eq!(HARVESTS.load(Ordering::SeqCst), 1);
+    assert!(!HARVESTING.lock().unwrap().contains_key(&key));
+  }
+
+  #[test]
+  fn test_etag() {
+    assert_eq!(
+      etag(b"feed"),
+      "\"yLwlhs3YfNb5cPxCYsS7xJFlgReIgl-8G6M2a_3kVBM\""
+    );
+  }
+}
@@ -1,16 +1,41 @@
+mod invidious;
 mod rss_piped;
 mod rss_ytextract;
 mod ytdlp;
 
 use async_trait::async_trait;
 
-#[allow(unused)]
+pub use invidious::Invidious;
 pub use rss_piped::RssPiped;
-pub use ytdlp::Ytdlp;
+pub use rss_ytextract::RssYtextract;
+pub use ytdlp::{
+  flush_episode_dates, load_episode_dates, Ytdlp, YtdlpPlaylist,
+};
 
-use crate::{podcast::Podcast, Result};
+use crate::{
+  config::HarvestorConfig, invidious::InvidiousInstance, piped::PipedInstance,
+  podcast::Podcast, Result,
+};
 
 #[async_trait]
 pub trait Harvestor {
   async fn harvest(&self, channel_id: &str) -> Result<Podcast>;
 }
+
+// none if the backend needs a piped instance but there is none
+pub fn build(
+  config: &HarvestorConfig,
+  piped: Option<&PipedInstance>,
+  invidious: &InvidiousInstance,
+) -> Option<Box<dyn Harvestor + Send + Sync>> {
+  match config {
+    HarvestorConfig::Ytdlp { max_episodes } => {
+      Some(Box::new(Ytdlp::new(*max_episodes)))
+    }
+    HarvestorConfig::RssPiped => Some(Box::new(RssPiped::new(piped?.clone()))),
+    HarvestorConfig::RssYtextract => Some(Box::new(RssYtextract::new())),
+    HarvestorConfig::Invidious => {
+      Some(Box::new(Invidious::new(invidious.clone())))
+    }
+  }
+}
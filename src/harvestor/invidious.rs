@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::{
+  chapters::chapters_url,
+  invidious::{InvidiousInstance, InvidiousInstanceRepo},
+  podcast::{AudioInfo, Episode, Podcast, Thumbnail},
+  transcript::transcript_url,
+  Result,
+};
+
+use super::Harvestor;
+
+// harvest a channel with the api of an invidious instance
+pub struct Invidious {
+  invidious: InvidiousInstance,
+}
+
+impl Invidious {
+  pub fn new(invidious: InvidiousInstance) -> Self {
+    Self { invidious }
+  }
+
+  async fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+    let resp = reqwest::Client::new()
+      .get(url)
+      .send()
+      .await
+      .and_then(|resp| resp.error_for_status())
+      .map_err(InvidiousInstanceRepo::notify_update)?;
+
+    Ok(resp.json().await?)
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Channel {
+  author: String,
+  author_id: String,
+  #[serde(default)]
+  description: String,
+  #[serde(default)]
+  author_thumbnails: Vec<InvidiousThumbnail>,
+  #[serde(default)]
+  tags: Vec<String>,
+}
+
+// older instances return the bare list of videos
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ChannelVideos {
+  Page { videos: Vec<Video> },
+  List(Vec<Video>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Video {
+  video_id: String,
+  title: String,
+  #[serde(default)]
+  description: String,
+  published: i64,
+  #[serde(default)]
+  length_seconds: u64,
+  #[serde(default)]
+  video_thumbnails: Vec<InvidiousThumbnail>,
+  #[serde(default)]
+  live_now: bool,
+  #[serde(default)]
+  is_upcoming: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct InvidiousThumbnail {
+  url: String,
+  width: u32,
+  height: u32,
+}
+
+#[async_trait]
+impl Harvestor for Invidious {
+  async fn harvest(&self, channel_id: &str) -> Result<Podcast> {
+    let channel_url = self.invidious.channel_url(channel_id);
+    let videos_url = self.invidious.channel_videos_url(channel_id);
+    let (channel, videos) = tokio::try_join!(
+      self.get::<Channel>(&channel_url),
+      self.get::<ChannelVideos>(&videos_url)
+    )?;
+
+    let videos = match videos {
+      ChannelVideos::Page { videos } | ChannelVideos::List(videos) => videos,
+    };
+    let episodes: Vec<_> = videos
+      .into_iter()
+      .filter(|v| !v.live_now && !v.is_upcoming)
+      .map(|v| self.make_episode(v, &channel.author))
+      .collect();
+
+    let last_build_date = episodes
+      .first()
+      .map(|e| e.pub_date.clone())
+      .unwrap_or_else(|| Utc::now().to_rfc2822());
+    let logo_url = self
+      .largest_thumbnail(channel.author_thumbnails)
+      .url
+      .clone();
+
+    Ok(Podcast {
+      title: channel.author.clone(),
+      description: channel.description,
+      last_build_date,
+      author: channel.author,
+      logo_url,
+      categories: channel.tags,
+      channel_url: format!(
+        "https://www.youtube.com/channel/{}",
+        channel.author_id
+      ),
+      episodes,
+      ..Default::default()
+    })
+  }
+}
+
+impl Invidious {
+  fn make_episode(&self, video: Video, author: &str) -> Episode {
+    let pub_date = Utc
+      .timestamp_opt(video.published, 0)
+      .single()
+      .unwrap_or_else(Utc::now)
+      .to_rfc2822();
+
+    Episode {
+      link: format!("https://www.youtube.com/watch?v={}", video.video_id),
+      audio_info: AudioInfo::for_video(&video.video_id),
+      chapters_url: Some(chapters_url(&video.video_id)),
+      transcript_url: Some(transcript_url(&video.video_id)),
+      thumbnail: self.largest_thumbnail(video.video_thumbnails),
+      guid: video.video_id.clone(),
+      video_id: video.video_id,
+      title: video.title,
+      description: video.description,
+      pub_date,
+      author: author.to_string(),
+      duration: video.length_seconds,
+      channel_id: None,
+    }
+  }
+
+  fn largest_thumbnail(
+    &self,
+    thumbnails: Vec<InvidiousThumbnail>,
+  ) -> Thumbnail {
+    thumbnails
+      .into_iter()
+      .max_by_key(|t| t.width)
+      .map(|t| Thumbnail {
+        url: self.invidious.absolute_url(&t.url),
+        width: t.width,
+        height: t.height,
+      })
+      .unwrap_or_default()
+  }
+}
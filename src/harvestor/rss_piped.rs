@@ -10,9 +10,11 @@ use regex::Regex;
 use serde::Deserialize;
 
 use crate::{
+  chapters::chapters_url,
   piped::{PipedInstance, PipedInstanceRepo},
   podcast::{AudioInfo, Episode, Podcast},
-  Error, Result, INSTANCE_PUBLIC_URL, W,
+  transcript::transcript_url,
+  Error, Result, W,
 };
 
 use super::Harvestor;
@@ -103,11 +105,9 @@ async fn make_episode(
   }
 
   let video_url = W(&entry).link()?;
-  let audio_info = AudioInfo {
-    url: format!("{}/audio/{}", &*INSTANCE_PUBLIC_URL, video_id),
-    mime_type: "audio/mpeg".to_string(),
-  };
+  let audio_info = AudioInfo::for_video(&video_id);
 
+  episode.video_id = video_id.clone();
   episode.title = entry.title.to_string();
   episode.link = video_url;
   episode.description = description;
@@ -120,6 +120,8 @@ async fn make_episode(
   episode.guid = entry.id;
   episode.thumbnail = thumbnail;
   episode.audio_info = audio_info;
+  episode.chapters_url = Some(chapters_url(&video_id));
+  episode.transcript_url = Some(transcript_url(&video_id));
 
   episode.duration = piped_stream.duration;
 
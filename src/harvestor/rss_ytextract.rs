@@ -4,9 +4,11 @@ use futures::{future::join, StreamExt};
 use ytextract::{Channel, Client, Video};
 
 use crate::{
+  chapters::chapters_url,
   podcast::{AudioInfo, Episode, Podcast, Thumbnail},
   rss::RssChannel,
-  Error, Result, INSTANCE_PUBLIC_URL,
+  transcript::transcript_url,
+  Error, Result,
 };
 
 use super::Harvestor;
@@ -16,8 +18,6 @@ pub struct RssYtextract {
 }
 
 impl RssYtextract {
-  // this harvestor backend may not be used
-  #[allow(unused)]
   pub fn new() -> Self {
     Self {
       client: Client::new(),
@@ -67,7 +67,6 @@ impl Harvestor for RssYtextract {
 
 fn make_episode(video: Video) -> Result<Episode> {
   let link = format!("https://www.youtube.com/watch?v={}", video.id());
-  let audio_link = format!("{}/audio/{}", &*INSTANCE_PUBLIC_URL, video.id());
 
   let date = video
     .date()
@@ -88,21 +87,25 @@ fn make_episode(video: Video) -> Result<Episode> {
       height: x.height as u32,
     })
     .unwrap_or_default();
-  let audio_info = AudioInfo {
-    url: audio_link,
-    mime_type: "audio/mpeg".into(),
-  };
+  let video_id = video.id().to_string();
+  let audio_info = AudioInfo::for_video(&video_id);
+  let chapters_url = Some(chapters_url(&video_id));
+  let transcript_url = Some(transcript_url(&video_id));
 
   let episode = Episode {
+    video_id: video_id.clone(),
     title: video.title().to_string(),
     link,
     description: video.description().to_string(),
     pub_date: date,
     author: "".into(),
-    guid: video.id().to_string(),
+    guid: video_id,
     duration: video.duration().as_secs_f64() as u64,
     thumbnail,
     audio_info,
+    chapters_url,
+    transcript_url,
+    channel_id: None,
   };
 
   Ok(episode)
@@ -1,20 +1,44 @@
-use std::sync::{Arc, LazyLock, RwLock};
+use std::{
+  collections::HashMap,
+  fs::{File, OpenOptions},
+  io::{BufRead as _, BufReader, BufWriter, Write as _},
+  path::Path,
+  sync::{Arc, LazyLock, Mutex, RwLock},
+};
 
 use crate::{
+  chapters::chapters_url,
+  extractor,
   podcast::{AudioInfo, Episode, Podcast},
-  Result, INSTANCE_PUBLIC_URL, YTDLP_MUTEX,
+  rss::RssChannel,
+  transcript::transcript_url,
+  util::acquire_ytdlp,
+  Error, Result, DATA_DIR,
 };
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use tokio::{process::Command};
+use serde::de::DeserializeOwned;
+use tokio::process::Command;
+use tracing::{info, warn};
 
 use super::Harvestor;
 
 // run yt-dlp command line to get audio stream directly.
 // requires yt-dlp executable to be in PATH.
-pub struct Ytdlp;
+pub struct Ytdlp {
+  max_episodes: usize,
+}
 
 impl Ytdlp {
+  pub fn new(max_episodes: usize) -> Self {
+    Self { max_episodes }
+  }
+}
+
+// same as `Ytdlp`, but harvests a playlist instead of a channel.
+pub struct YtdlpPlaylist;
+
+impl YtdlpPlaylist {
   pub fn new() -> Self {
     Self
   }
@@ -31,16 +55,6 @@ struct Channel {
   uploader_id: String,
 }
 impl Channel {
-  fn squarest_thumbnail(&self) -> Option<Thumbnail> {
-    self
-      .thumbnails
-      .iter()
-      .filter(|t| t.width > 0 && t.height > 0)
-      // first by aspect ratio, then by largest width
-      .min_by_key(|t| ((t.width - t.height).abs(), -t.width))
-      .cloned()
-  }
-
   fn last_episode_date(&self) -> String {
     self
       .entries
@@ -53,14 +67,39 @@ impl Channel {
   }
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct Playlist {
+  id: String,
+  title: String,
+  description: Option<String>,
+  #[serde(default)]
+  tags: Vec<String>,
+  entries: Vec<Option<Entry>>,
+  #[serde(default)]
+  thumbnails: Vec<Thumbnail>,
+  channel: Option<String>,
+  uploader_id: Option<String>,
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct Entry {
   id: String,
   title: String,
   url: String,
   description: Option<String>,
+  #[serde(default)]
   thumbnails: Vec<Thumbnail>,
-  duration: f32,
+  duration: Option<f32>,
+  // only listed for the entries of playlists
+  channel_id: Option<String>,
+}
+
+impl Entry {
+  // playlists keep listing videos that were made private or deleted
+  // after being added.
+  fn is_available(&self) -> bool {
+    !matches!(self.title.as_str(), "[Private video]" | "[Deleted video]")
+  }
 }
 
 #[derive(Debug, serde::Deserialize, Clone, Default)]
@@ -76,30 +115,93 @@ struct Thumbnail {
 impl Harvestor for Ytdlp {
   async fn harvest(&self, channel_id: &str) -> Result<Podcast> {
     let url = format!("https://youtube.com/channel/{}/videos", channel_id);
-    let mut cmd = Command::new("yt-dlp");
-    cmd
-      // don't fetch video pages
-      .arg("--flat-playlist")
-      // emit the output as a single json object instead of jsonl
-      .arg("--dump-single-json")
-      // only fetch the latest 20 videos
-      .arg("--playlist-end")
-      .arg("20")
-      .arg(url);
-
-    let guard = YTDLP_MUTEX.acquire().await.unwrap();
-    let stdout = cmd.output().await?.stdout;
-    drop(guard);
-
-    let channel: Channel = serde_json::from_slice(&stdout)?;
+    let (channel, rss_channel) = tokio::join!(
+      // only fetch the latest videos
+      dump_flat_playlist::<Channel>(&url, self.max_episodes),
+      RssChannel::fetch(channel_id)
+    );
+    let channel = channel?;
+
+    // the atom feed has the real publish dates of the latest 15 videos.
+    // Record them so older episodes keep them after dropping out of the
+    // feed, while the rest falls back to the date we first saw them.
+    match rss_channel {
+      Ok(rss_channel) => {
+        for episode in rss_channel.episodes {
+          if let Some(published) = episode.published {
+            GLOBAL_EPISODE_DATE_REGISTRY.set(&episode.video_id, published);
+          }
+        }
+      }
+      Err(e) => {
+        warn!("failed fetching atom feed of {}: {}", channel_id, e);
+      }
+    }
 
     Ok(channel.into())
   }
 }
 
+#[async_trait]
+impl Harvestor for YtdlpPlaylist {
+  async fn harvest(&self, playlist_id: &str) -> Result<Podcast> {
+    let url = format!("https://youtube.com/playlist?list={}", playlist_id);
+    // playlists are read from the start, so allow for longer series
+    let playlist: Playlist = dump_flat_playlist(&url, 200).await?;
+
+    Ok(playlist.into())
+  }
+}
+
+async fn dump_flat_playlist<T: DeserializeOwned>(
+  url: &str,
+  playlist_end: usize,
+) -> Result<T> {
+  let mut cmd = Command::new("yt-dlp");
+  cmd
+    // don't fetch video pages
+    .arg("--flat-playlist")
+    // emit the output as a single json object instead of jsonl
+    .arg("--dump-single-json")
+    .arg("--playlist-end")
+    .arg(playlist_end.to_string())
+    .arg(url);
+
+  let guard = acquire_ytdlp(format!("harvest {url}")).await?;
+  let output = cmd.output().await?;
+  drop(guard);
+
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // e.g. a terminated channel
+    if let Some(e) = extractor::classify(&stderr) {
+      return Err(e.into());
+    }
+    let message = format!(
+      "yt-dlp exited with code ({:?}): {}",
+      output.status.code(),
+      stderr
+    );
+    return Err(Error::Harvest(message));
+  }
+
+  Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+fn squarest_thumbnail(thumbnails: &[Thumbnail]) -> Option<Thumbnail> {
+  thumbnails
+    .iter()
+    .filter(|t| t.width > 0 && t.height > 0)
+    // first by aspect ratio, then by largest width
+    .min_by_key(|t| ((t.width - t.height).abs(), -t.width))
+    .cloned()
+}
+
 impl From<Channel> for Podcast {
   fn from(c: Channel) -> Self {
-    let logo_url = c.squarest_thumbnail().map(|t| t.url).unwrap_or_default();
+    let logo_url = squarest_thumbnail(&c.thumbnails)
+      .map(|t| t.url)
+      .unwrap_or_default();
     let last_episode_date = c.last_episode_date();
     let episodes = c.entries.into_iter().flatten().map(Into::into).collect();
 
@@ -117,32 +219,92 @@ impl From<Channel> for Podcast {
   }
 }
 
+impl From<Playlist> for Podcast {
+  fn from(p: Playlist) -> Self {
+    playlist_podcast(p, &GLOBAL_EPISODE_DATE_REGISTRY)
+  }
+}
+
+fn playlist_podcast(p: Playlist, registry: &EpisodeDateRegistry) -> Podcast {
+  let logo_url = squarest_thumbnail(&p.thumbnails)
+    .map(|t| t.url)
+    .unwrap_or_default();
+  let entries: Vec<Entry> = p
+    .entries
+    .into_iter()
+    .flatten()
+    .filter(Entry::is_available)
+    .collect();
+
+  // the registry dates of a newly seen playlist are all about the same,
+  // so podcast apps would sort them arbitrarily. Spread the dates out
+  // from the earliest one instead to keep the playlist order. Videos
+  // appended to the playlist later will still show up as the newest.
+  let first_date = entries
+    .iter()
+    .map(|e| registry.get(&e.id))
+    .min()
+    .unwrap_or_else(Utc::now);
+  let episode_date =
+    |i: usize| first_date + chrono::Duration::minutes(i as i64);
+
+  let last_build_date =
+    episode_date(entries.len().saturating_sub(1)).to_rfc2822();
+  let episodes = entries
+    .into_iter()
+    .enumerate()
+    .map(|(i, e)| Episode {
+      pub_date: episode_date(i).to_rfc2822(),
+      ..entry_episode(e, registry)
+    })
+    .collect();
+
+  Podcast {
+    title: p.title,
+    description: p.description.unwrap_or_default(),
+    last_build_date,
+    language: String::from("en"),
+    author: p.channel.or(p.uploader_id).unwrap_or_default(),
+    categories: p.tags,
+    channel_url: format!("https://www.youtube.com/playlist?list={}", p.id),
+    episodes,
+    logo_url,
+  }
+}
+
 impl From<Entry> for Episode {
   fn from(e: Entry) -> Self {
-    let thumbnail = e
-      .thumbnails
-      .into_iter()
-      .max_by_key(|t| t.width)
-      .unwrap_or_default()
-      .into();
+    entry_episode(e, &GLOBAL_EPISODE_DATE_REGISTRY)
+  }
+}
 
-    let audio_info = AudioInfo {
-      url: format!("{}/audio/{}", &*INSTANCE_PUBLIC_URL, e.id),
-      mime_type: "audio/mpeg".to_string(),
-    };
-    let pub_date = GLOBAL_EPISODE_DATE_REGISTRY.get(&e.id).to_rfc2822();
+fn entry_episode(e: Entry, registry: &EpisodeDateRegistry) -> Episode {
+  let thumbnail = e
+    .thumbnails
+    .into_iter()
+    .max_by_key(|t| t.width)
+    .unwrap_or_default()
+    .into();
 
-    Self {
-      title: e.title,
-      link: e.url,
-      description: e.description.unwrap_or_default(),
-      author: "".to_string(),
-      duration: e.duration as u64,
-      guid: e.id,
-      thumbnail,
-      pub_date,
-      audio_info,
-    }
+  let audio_info = AudioInfo::for_video(&e.id);
+  let chapters_url = Some(chapters_url(&e.id));
+  let transcript_url = Some(transcript_url(&e.id));
+  let pub_date = registry.get(&e.id).to_rfc2822();
+
+  Episode {
+    video_id: e.id.clone(),
+    title: e.title,
+    link: e.url,
+    description: e.description.unwrap_or_default(),
+    author: "".to_string(),
+    duration: e.duration.unwrap_or_default() as u64,
+    guid: e.id,
+    thumbnail,
+    pub_date,
+    audio_info,
+    chapters_url,
+    transcript_url,
+    channel_id: e.channel_id,
   }
 }
 
@@ -161,22 +323,132 @@ impl From<Thumbnail> for crate::podcast::Thumbnail {
 // video without much overhead as to fetch the video page
 #[derive(Debug, Default, Clone)]
 struct EpisodeDateRegistry {
-  dict: Arc<RwLock<std::collections::HashMap<String, DateTime<Utc>>>>,
+  dict: Arc<RwLock<HashMap<String, DateTime<Utc>>>>,
+  // the dates are appended to this file as "<id>\t<rfc3339 date>" lines
+  // so that they survive restarts.
+  file: Option<Arc<Mutex<File>>>,
 }
 
 static GLOBAL_EPISODE_DATE_REGISTRY: LazyLock<EpisodeDateRegistry> =
-  LazyLock::new(Default::default);
+  LazyLock::new(|| {
+    let path = Path::new(DATA_DIR.as_str()).join("episode-dates.tsv");
+    EpisodeDateRegistry::load(&path).unwrap_or_else(|e| {
+      warn!(
+        "failed loading episode dates from {}: {}",
+        path.display(),
+        e
+      );
+      Default::default()
+    })
+  });
+
+pub fn load_episode_dates() {
+  LazyLock::force(&GLOBAL_EPISODE_DATE_REGISTRY);
+}
+
+// make sure the dates are on disk before exiting
+pub fn flush_episode_dates() {
+  GLOBAL_EPISODE_DATE_REGISTRY.flush();
+}
 
 impl EpisodeDateRegistry {
+  fn load(path: &Path) -> Result<Self> {
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir)?;
+    }
+
+    let mut dict = HashMap::new();
+    let mut lines = 0;
+    if path.exists() {
+      for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        lines += 1;
+        let Some((id, date)) = line.split_once('\t') else {
+          continue;
+        };
+        let Ok(date) = DateTime::parse_from_rfc3339(date) else {
+          continue;
+        };
+        dict.insert(id.to_string(), date.with_timezone(&Utc));
+      }
+    }
+
+    info!(
+      "loaded {} episode dates from {}",
+      dict.len(),
+      path.display()
+    );
+    if lines > dict.len() {
+      compact(path, &dict)?;
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    Ok(Self {
+      dict: Arc::new(RwLock::new(dict)),
+      file: Some(Arc::new(Mutex::new(file))),
+    })
+  }
+
   fn get(&self, id: &str) -> DateTime<Utc> {
     if let Some(date) = self.dict.read().unwrap().get(id).cloned() {
       return date;
     }
 
+    let mut dict = self.dict.write().unwrap();
+    if let Some(date) = dict.get(id).cloned() {
+      return date;
+    }
+
     let date = Utc::now();
-    self.dict.write().unwrap().insert(id.to_string(), date);
+    dict.insert(id.to_string(), date);
+    self.persist(id, date);
     date
   }
+
+  fn set(&self, id: &str, date: DateTime<Utc>) {
+    let mut dict = self.dict.write().unwrap();
+    if dict.get(id) == Some(&date) {
+      return;
+    }
+
+    dict.insert(id.to_string(), date);
+    self.persist(id, date);
+  }
+
+  fn flush(&self) {
+    let Some(file) = &self.file else {
+      return;
+    };
+
+    if let Err(e) = file.lock().unwrap().sync_all() {
+      warn!("failed flushing episode dates: {}", e);
+    }
+  }
+
+  fn persist(&self, id: &str, date: DateTime<Utc>) {
+    let Some(file) = &self.file else {
+      return;
+    };
+
+    let mut file = file.lock().unwrap();
+    if let Err(e) = writeln!(file, "{}\t{}", id, date.to_rfc3339()) {
+      warn!("failed persisting episode date of {}: {}", id, e);
+    }
+  }
+}
+
+// rewrite the file with one line per episode, dropping the dates that
+// were replaced since and the broken lines
+fn compact(path: &Path, dict: &HashMap<String, DateTime<Utc>>) -> Result<()> {
+  let tmp_path = path.with_extension("tsv.tmp");
+  let mut file = BufWriter::new(File::create(&tmp_path)?);
+  for (id, date) in dict {
+    writeln!(file, "{}\t{}", id, date.to_rfc3339())?;
+  }
+  file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+  std::fs::rename(&tmp_path, path)?;
+  Ok(())
 }
 
 #[cfg(test)]
@@ -188,11 +460,41 @@ mod tests {
     let registry = EpisodeDateRegistry::default();
     let date = registry.get("test");
     assert_eq!(registry.get("test"), date);
+
+    // a known publish date takes precedence over the stamped one
+    let published = date - chrono::Duration::days(3);
+    registry.set("test", published);
+    assert_eq!(registry.get("test"), published);
+  }
+
+  #[test]
+  fn test_episode_date_registry_persistence() {
+    let dir = std::env::temp_dir()
+      .join(format!("episode-date-registry-{}", std::process::id()));
+    let path = dir.join("episode-dates.tsv");
+    std::fs::remove_dir_all(&dir).ok();
+
+    let registry = EpisodeDateRegistry::load(&path).unwrap();
+    let date = registry.get("test");
+    let published = date - chrono::Duration::days(3);
+    registry.set("published", date);
+    registry.set("published", published);
+
+    // a reloaded registry must return the dates recorded before
+    let reloaded = EpisodeDateRegistry::load(&path).unwrap();
+    assert_eq!(reloaded.get("test"), date);
+    assert_eq!(reloaded.get("published"), published);
+    assert_eq!(reloaded.dict.read().unwrap().len(), 2);
+    // without the replaced date
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content.lines().count(), 2);
+
+    std::fs::remove_dir_all(&dir).ok();
   }
 
   #[tokio::test]
   async fn test_channel() {
-    let harvestor = Ytdlp::new();
+    let harvestor = Ytdlp::new(20);
     let podcast = harvestor.harvest("UC1yNl2E66ZzKApQdRuTQ4tw").await;
     dbg!(&podcast);
     assert!(podcast.is_ok());
@@ -200,4 +502,39 @@ mod tests {
     let podcast = podcast.unwrap();
     assert_eq!(podcast.title, "Sabine Hossenfelder");
   }
+
+  #[test]
+  fn test_playlist_keeps_order() {
+    let playlist: Playlist = serde_json::from_value(serde_json::json!({
+      "id": "PLtest",
+      "title": "Lectures",
+      "entries": [
+        {"id": "lecture-1", "title": "Lecture 1", "url": "u1", "duration": 60.0,
+         "channel_id": "UCa"},
+        {"id": "gone", "title": "[Private video]", "url": "u2", "duration": null},
+        {"id": "lecture-2", "title": "Lecture 2", "url": "u3", "duration": 60.0},
+        {"id": "lecture-3", "title": "Lecture 3", "url": "u4", "duration": 60.0},
+      ],
+    }))
+    .unwrap();
+
+    let dir = std::env::temp_dir()
+      .join(format!("playlist-keeps-order-{}", std::process::id()));
+    let path = dir.join("episode-dates.tsv");
+    std::fs::remove_dir_all(&dir).ok();
+    let registry = EpisodeDateRegistry::load(&path).unwrap();
+
+    let podcast = playlist_podcast(playlist, &registry);
+    std::fs::remove_dir_all(&dir).ok();
+    let ids: Vec<_> = podcast.episodes.iter().map(|e| &e.guid).collect();
+    assert_eq!(ids, ["lecture-1", "lecture-2", "lecture-3"]);
+    assert_eq!(podcast.episodes[0].channel_id.as_deref(), Some("UCa"));
+
+    let dates: Vec<_> = podcast
+      .episodes
+      .iter()
+      .map(|e| DateTime::parse_from_rfc2822(&e.pub_date).unwrap())
+      .collect();
+    assert!(dates.windows(2).all(|w| w[0] < w[1]));
+  }
 }
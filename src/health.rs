@@ -0,0 +1,309 @@
+use std::{
+  collections::HashMap,
+  fmt,
+  sync::{LazyLock, Mutex},
+  time::{Duration, Instant},
+};
+
+use futures::Future;
+use tracing::{info, warn};
+
+use crate::{metrics::METRICS, Error, Result};
+
+// open the circuit of a backend after this many failures in a row
+static HEALTH_FAILURE_THRESHOLD: LazyLock<u32> = LazyLock::new(|| {
+  std::env::var("HEALTH_FAILURE_THRESHOLD")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .filter(|n| *n > 0)
+    .unwrap_or(5)
+});
+
+// probe a backend with an open circuit this often (in seconds) to see
+// if it has recovered, with a request or in the background
+static HEALTH_PROBE_INTERVAL: LazyLock<Duration> = LazyLock::new(|| {
+  let secs = std::env::var("HEALTH_PROBE_INTERVAL")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(60);
+  Duration::from_secs(secs)
+});
+
+pub static HEALTH: LazyLock<HealthTracker> = LazyLock::new(|| {
+  HealthTracker::new(*HEALTH_FAILURE_THRESHOLD, *HEALTH_PROBE_INTERVAL)
+});
+
+// weight of the latest outcome in the moving averages
+const EWMA_WEIGHT: f64 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+  Harvestor,
+  Extractor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BackendId {
+  pub kind: BackendKind,
+  pub name: &'static str,
+}
+
+impl BackendId {
+  pub fn harvestor(name: &'static str) -> Self {
+    let kind = BackendKind::Harvestor;
+    Self { kind, name }
+  }
+
+  pub fn extractor(name: &'static str) -> Self {
+    let kind = BackendKind::Extractor;
+    Self { kind, name }
+  }
+}
+
+impl fmt::Display for BackendId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.kind {
+      BackendKind::Harvestor => write!(f, "harvestor {}", self.name),
+      BackendKind::Extractor => write!(f, "extractor {}", self.name),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct BackendHealth {
+  // moving average of 1.0 for success and 0.0 for failure
+  success_rate: f64,
+  // moving average of successful calls
+  latency: Option<Duration>,
+  consecutive_failures: u32,
+  // set while the circuit is open, reset on every probe
+  opened_at: Option<Instant>,
+}
+
+impl Default for BackendHealth {
+  fn default() -> Self {
+    // untried backends are assumed healthy
+    Self {
+      success_rate: 1.0,
+      latency: None,
+      consecutive_failures: 0,
+      opened_at: None,
+    }
+  }
+}
+
+pub struct HealthTracker {
+  failure_threshold: u32,
+  probe_interval: Duration,
+  backends: Mutex<HashMap<BackendId, BackendHealth>>,
+}
+
+impl HealthTracker {
+  fn new(failure_threshold: u32, probe_interval: Duration) -> Self {
+    Self {
+      failure_threshold,
+      probe_interval,
+      backends: Mutex::new(HashMap::new()),
+    }
+  }
+
+  // drop the backends with an open circuit and put the healthiest
+  // first. backends with the same score keep their configured order.
+  // if every circuit is open, all backends are tried anyway.
+  pub fn select<T>(
+    &self,
+    backends: Vec<T>,
+    id: impl Fn(&T) -> BackendId,
+  ) -> Vec<T> {
+    let mut health = self.backends.lock().unwrap();
+    let now = Instant::now();
+
+    let mut scored = vec![];
+    let mut rejected = vec![];
+    for backend in backends {
+      let entry = health.entry(id(&backend)).or_default();
+      match entry.opened_at {
+        None => scored.push((score(entry), backend)),
+        // half-open: let a single request through as a probe. a probe
+        // that never finishes is retried after another interval.
+        Some(opened_at) if now - opened_at >= self.probe_interval => {
+          entry.opened_at = Some(now);
+          scored.push((score(entry), backend));
+        }
+        Some(_) => rejected.push(backend),
+      }
+    }
+
+    if scored.is_empty() {
+      return rejected;
+    }
+
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, backend)| backend).collect()
+  }
+
+  pub fn probe_interval(&self) -> Duration {
+    self.probe_interval
+  }
+
+  // take the probe of a backend whose circuit has been open for a probe
+  // interval, so requests don't probe it at the same time
+  pub fn claim_probe(&self, id: BackendId) -> bool {
+    let mut health = self.backends.lock().unwrap();
+    let Some(entry) = health.get_mut(&id) else {
+      return false;
+    };
+    let now = Instant::now();
+    match entry.opened_at {
+      Some(opened_at) if now - opened_at >= self.probe_interval => {
+        entry.opened_at = Some(now);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  // run the call and record its outcome
+  pub async fn track<A>(
+    &self,
+    id: BackendId,
+    fut: impl Future<Output = Result<A>>,
+  ) -> Result<A> {
+    let start = Instant::now();
+    let result = fut.await;
+    record_metrics(id, &result, start.elapsed());
+    match &result {
+      Ok(_) => self.record_success(id, start.elapsed()),
+      // not the backend's fault
+      Err(Error::Ytdlp(e)) if e.is_unavailable() => {}
+      Err(e) => self.record_failure(id, e),
+    }
+    result
+  }
+
+  fn record_success(&self, id: BackendId, latency: Duration) {
+    let mut health = self.backends.lock().unwrap();
+    let entry = health.entry(id).or_default();
+
+    entry.success_rate += EWMA_WEIGHT * (1.0 - entry.success_rate);
+    entry.latency = Some(match entry.latency {
+      Some(avg) => {
+        avg.mul_f64(1.0 - EWMA_WEIGHT) + latency.mul_f64(EWMA_WEIGHT)
+      }
+      None => latency,
+    });
+    entry.consecutive_failures = 0;
+
+    if entry.opened_at.take().is_some() {
+      info!("{id} recovered, closing its circuit");
+    }
+  }
+
+  fn record_failure(&self, id: BackendId, error: &Error) {
+    let mut health = self.backends.lock().unwrap();
+    let entry = health.entry(id).or_default();
+
+    entry.success_rate -= EWMA_WEIGHT * entry.success_rate;
+    entry.consecutive_failures += 1;
+
+    if entry.opened_at.is_some() {
+      // a failed probe, wait for another interval
+      entry.opened_at = Some(Instant::now());
+    } else if entry.consecutive_failures >= self.failure_threshold {
+      warn!(
+        "{id} failed {} times in a row ({error}), opening its circuit",
+        entry.consecutive_failures
+      );
+      entry.opened_at = Some(Instant::now());
+    }
+  }
+}
+
+fn record_metrics<A>(id: BackendId, result: &Result<A>, latency: Duration) {
+  let kind = match id.kind {
+    BackendKind::Harvestor => "harvestor",
+    BackendKind::Extractor => "extractor",
+  };
+  let outcome = if result.is_ok() { "success" } else { "failure" };
+
+  let labels = [("kind", kind), ("backend", id.name)];
+  METRICS.observe("backend_request_duration_seconds", &labels, latency);
+  let labels = [("kind", kind), ("backend", id.name), ("outcome", outcome)];
+  METRICS.inc("backend_requests_total", &labels);
+}
+
+// success rate in percent, rounded to steps of 10 so that small
+// differences don't reorder the configured chain
+fn score(health: &BackendHealth) -> u32 {
+  (health.success_rate * 10.0).round() as u32 * 10
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  const A: BackendId = BackendId {
+    kind: BackendKind::Extractor,
+    name: "a",
+  };
+  const B: BackendId = BackendId {
+    kind: BackendKind::Extractor,
+    name: "b",
+  };
+
+  async fn call(tracker: &HealthTracker, id: BackendId, ok: bool) {
+    let fut = async move {
+      match ok {
+        true => Ok(()),
+        false => Err(Error::BackendTimeout(id.name)),
+      }
+    };
+    tracker.track(id, fut).await.ok();
+  }
+
+  #[tokio::test]
+  async fn test_circuit_breaker() {
+    let tracker = HealthTracker::new(3, Duration::from_millis(50));
+    let select = || tracker.select(vec![A, B], |id| *id);
+
+    assert_eq!(select(), [A, B]);
+
+    // a failing backend is moved behind the healthy one
+    call(&tracker, A, false).await;
+    assert_eq!(select(), [B, A]);
+
+    // and skipped once its circuit is open
+    call(&tracker, A, false).await;
+    call(&tracker, A, false).await;
+    assert_eq!(select(), [B]);
+
+    // unless all of them are open
+    for _ in 0..3 {
+      call(&tracker, B, false).await;
+    }
+    assert_eq!(select(), [A, B]);
+
+    // after the probe interval, a single probe is let through
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(select(), [A, B]);
+    call(&tracker, A, true).await;
+    call(&tracker, B, false).await;
+    assert_eq!(select(), [A]);
+  }
+
+  #[tokio::test]
+  async fn test_claim_probe() {
+    let tracker = HealthTracker::new(1, Duration::from_millis(50));
+
+    // closed circuits don't need probing
+    assert!(!tracker.claim_probe(A));
+    call(&tracker, A, false).await;
+    assert!(!tracker.claim_probe(A));
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(tracker.claim_probe(A));
+    // requests don't probe it meanwhile
+    assert_eq!(tracker.select(vec![A, B], |id| *id), [B]);
+    assert!(!tracker.claim_probe(A));
+  }
+}
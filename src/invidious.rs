@@ -0,0 +1,396 @@
+use std::{
+  convert::Infallible,
+  sync::{Arc, Mutex, RwLock},
+  time::Duration,
+};
+
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Query};
+use once_cell::sync::Lazy;
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+use tokio::{
+  sync::mpsc::{channel, Receiver, Sender},
+  task::JoinSet,
+};
+use tracing::{info, warn};
+
+const DEFAULT_INVIDIOUS_INSTANCE: &str = "https://inv.nadeko.net";
+
+const INVIDIOUS_INSTANCES_URL: &str =
+  "https://api.invidious.io/instances.json?sort_by=health";
+
+const INVIDIOUS_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InvidiousInstance {
+  api_url: String,
+}
+
+impl InvidiousInstance {
+  fn new(api_url: String) -> Self {
+    let api_url = api_url.trim_end_matches('/').to_string();
+    Self { api_url }
+  }
+
+  pub fn channel_url(&self, channel_id: &str) -> String {
+    format!("{}/api/v1/channels/{}", self.api_url, channel_id)
+  }
+
+  pub fn channel_videos_url(&self, channel_id: &str) -> String {
+    format!("{}/api/v1/channels/{}/videos", self.api_url, channel_id)
+  }
+
+  pub fn video_url(&self, video_id: &str) -> String {
+    format!("{}/api/v1/videos/{}", self.api_url, video_id)
+  }
+
+  // the api returns some urls relative to the instance
+  pub fn absolute_url(&self, url: &str) -> String {
+    if url.starts_with('/') {
+      format!("{}{}", self.api_url, url)
+    } else {
+      url.to_string()
+    }
+  }
+
+  fn stats_url(&self) -> String {
+    format!("{}/api/v1/stats", self.api_url)
+  }
+}
+
+impl Default for InvidiousInstance {
+  fn default() -> Self {
+    InvidiousInstanceRepo::instance()
+  }
+}
+
+#[derive(Deserialize)]
+struct InvidiousInstanceQuery {
+  invidious_instance: String,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for InvidiousInstance
+where
+  S: Send + Sync,
+{
+  type Rejection = Infallible;
+
+  async fn from_request_parts(
+    parts: &mut http::request::Parts,
+    state: &S,
+  ) -> Result<Self, Self::Rejection> {
+    // extract &invidious_instance=<value> from URL or use global instance.
+    let instance =
+      Query::<InvidiousInstanceQuery>::from_request_parts(parts, state)
+        .await
+        .map(|query| InvidiousInstance::new(query.0.invidious_instance))
+        .unwrap_or_default();
+
+    Ok(instance)
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct InvidiousInstanceStat {
+  instance: InvidiousInstance,
+  name: String,
+  region: Option<String>,
+  latency: Option<u64>,
+}
+
+pub struct InvidiousInstanceRepo {
+  instances_url: String,
+  current_instance: Mutex<InvidiousInstance>,
+  update_signal: Sender<()>,
+  update_receiver: RwLock<Option<Receiver<()>>>,
+  interval: Duration,
+}
+
+impl Default for InvidiousInstanceRepo {
+  fn default() -> Self {
+    // poll a new instance every 4 hours
+    Self::new(Duration::from_secs(240 * 60))
+  }
+}
+
+static GLOBAL_REPO: Lazy<InvidiousInstanceRepo> = Lazy::new(Default::default);
+
+impl InvidiousInstanceRepo {
+  pub fn global() -> &'static Self {
+    &GLOBAL_REPO
+  }
+
+  pub fn instance() -> InvidiousInstance {
+    GLOBAL_REPO.current_instance.lock().unwrap().clone()
+  }
+
+  pub fn notify_update<E: std::error::Error>(e: E) -> E {
+    eprintln!("Failed requesting invidious: {e:?}, refreshing");
+    GLOBAL_REPO.update_signal.try_send(()).ok();
+    e
+  }
+
+  fn new(interval: Duration) -> Self {
+    let (update_signal, update_receiver) = channel(1);
+    let update_receiver = RwLock::new(Some(update_receiver));
+
+    Self {
+      instances_url: INVIDIOUS_INSTANCES_URL.to_string(),
+      current_instance: Mutex::new(InvidiousInstance::new(
+        DEFAULT_INVIDIOUS_INSTANCE.to_string(),
+      )),
+      update_signal,
+      update_receiver,
+      interval,
+    }
+  }
+
+  pub async fn run() {
+    let this = Self::global();
+
+    let mut receiver = this
+      .update_receiver
+      .write()
+      .expect("invidious instance repo not run as singleton")
+      .take()
+      .unwrap();
+
+    loop {
+      let Ok(instances) = this.pull_latest().await else {
+        warn!(
+          "Failed pulling latest invidious instances. Retrying after {:?}",
+          this.interval
+        );
+        tokio::time::sleep(this.interval).await;
+        continue;
+      };
+      let instances = check_latency(&instances).await;
+
+      match instances.into_iter().find(|x| x.latency.is_some()) {
+        Some(stat) => {
+          let mut global = this.current_instance.lock().unwrap();
+          *global = stat.instance;
+          info!("Selected new invidious instance: {}", &global.api_url);
+        }
+        None => {
+          warn!(
+            "No available invidious instances found. Retrying after {:?}",
+            this.interval
+          );
+        }
+      }
+
+      tokio::select! {
+        () = tokio::time::sleep(this.interval) => {},
+        _ = receiver.recv() => {},
+      }
+    }
+  }
+
+  async fn pull_latest(&self) -> anyhow::Result<Vec<InvidiousInstanceStat>> {
+    let json = reqwest::get(&self.instances_url).await?.text().await?;
+    let mut instances = parse_instances(&json)?;
+    instances.shuffle(&mut rand::thread_rng());
+    Ok(instances)
+  }
+}
+
+// the instance list is an array of [name, details] pairs
+fn parse_instances(json: &str) -> anyhow::Result<Vec<InvidiousInstanceStat>> {
+  #[derive(Deserialize)]
+  struct Details {
+    uri: String,
+    region: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+    api: Option<bool>,
+  }
+
+  let list: Vec<(String, Details)> = serde_json::from_str(json)?;
+  let instances = list
+    .into_iter()
+    // onion and i2p instances are unreachable, and some instances
+    // disable the api
+    .filter(|(_, d)| d.kind == "https" && d.api == Some(true))
+    .map(|(name, d)| InvidiousInstanceStat {
+      instance: InvidiousInstance::new(d.uri),
+      name,
+      region: d.region,
+      latency: None,
+    })
+    .collect();
+
+  Ok(instances)
+}
+
+async fn check_latency(
+  instances: &[InvidiousInstanceStat],
+) -> Vec<InvidiousInstanceStat> {
+  let client = Arc::new(
+    reqwest::Client::builder()
+      .timeout(INVIDIOUS_PROBE_TIMEOUT)
+      .build()
+      .unwrap(),
+  );
+
+  let mut tasks = JoinSet::new();
+  for stat in instances {
+    let mut stat = stat.clone();
+    let client = client.clone();
+    tasks.spawn(async move {
+      let start = tokio::time::Instant::now();
+      let url = stat.instance.stats_url();
+      match client.get(&url).send().await {
+        Ok(resp) if resp.status().is_success() => {
+          let elapsed = start.elapsed().as_millis() as u64;
+          stat.latency = Some(elapsed);
+        }
+        _ => {}
+      }
+      stat
+    });
+  }
+
+  let mut output = vec![];
+  while let Some(stat) = tasks.join_next().await {
+    if let Ok(stat) = stat {
+      output.push(stat);
+    }
+  }
+
+  output.sort_by_key(|x| x.latency.unwrap_or(u64::MAX));
+  output
+}
+
+#[cfg(test)]
+mod test {
+  use std::net::SocketAddr;
+
+  use axum::{extract::Path, routing::get, Json, Router};
+  use serde_json::{json, Value};
+
+  use super::*;
+  use crate::{
+    extractor::{self, Extraction, Extractor as _},
+    harvestor::{self, Harvestor as _},
+  };
+
+  // a local stand-in for the invidious api
+  async fn fake_server() -> InvidiousInstance {
+    async fn channel(Path(id): Path<String>) -> Json<Value> {
+      Json(json!({
+        "author": "Fake Channel",
+        "authorId": id,
+        "description": "a channel for testing",
+        "authorThumbnails": [
+          {"url": "https://img.test/small.jpg", "width": 32, "height": 32},
+          {"url": "https://img.test/large.jpg", "width": 512, "height": 512}
+        ],
+        "tags": ["testing"]
+      }))
+    }
+
+    async fn videos() -> Json<Value> {
+      Json(json!({
+        "videos": [
+          {
+            "videoId": "video2",
+            "title": "Second",
+            "description": "the second video",
+            "published": 1700086400,
+            "lengthSeconds": 120,
+            "videoThumbnails": [
+              {"url": "/vi/video2/hq.jpg", "width": 480, "height": 360}
+            ]
+          },
+          {
+            "videoId": "upcoming",
+            "title": "Upcoming",
+            "published": 1800000000,
+            "lengthSeconds": 0,
+            "isUpcoming": true
+          },
+          {
+            "videoId": "video1",
+            "title": "First",
+            "published": 1700000000,
+            "lengthSeconds": 60
+          }
+        ]
+      }))
+    }
+
+    async fn video(Path(id): Path<String>) -> Json<Value> {
+      Json(json!({
+        "videoId": id,
+        "adaptiveFormats": [
+          {"url": "/videoplayback?itag=137", "type": "video/mp4", "bitrate": "4000000"},
+          {"url": "/videoplayback?itag=139", "type": "audio/mp4; codecs=\"mp4a.40.5\"", "bitrate": "48000"},
+          {"url": "/videoplayback?itag=140", "type": "audio/mp4; codecs=\"mp4a.40.2\"", "bitrate": "130000"},
+          {"url": "/videoplayback?itag=251", "type": "audio/webm; codecs=\"opus\"", "bitrate": "160000"}
+        ]
+      }))
+    }
+
+    let app = Router::new()
+      .route("/api/v1/channels/:id", get(channel))
+      .route("/api/v1/channels/:id/videos", get(videos))
+      .route("/api/v1/videos/:id", get(video));
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let server = axum::Server::bind(&addr).serve(app.into_make_service());
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    InvidiousInstance::new(url)
+  }
+
+  #[tokio::test]
+  async fn test_harvest() {
+    let instance = fake_server().await;
+    let podcast = harvestor::Invidious::new(instance.clone())
+      .harvest("UCfake")
+      .await
+      .unwrap();
+
+    assert_eq!(podcast.title, "Fake Channel");
+    assert_eq!(podcast.logo_url, "https://img.test/large.jpg");
+    let ids: Vec<_> = podcast.episodes.iter().map(|e| &e.video_id).collect();
+    assert_eq!(ids, ["video2", "video1"]);
+    assert_eq!(podcast.episodes[0].duration, 120);
+    assert_eq!(
+      podcast.episodes[0].thumbnail.url,
+      instance.absolute_url("/vi/video2/hq.jpg")
+    );
+    assert_eq!(podcast.last_build_date, podcast.episodes[0].pub_date);
+  }
+
+  #[tokio::test]
+  async fn test_extract() {
+    let instance = fake_server().await;
+    let extraction = extractor::Invidious(&instance)
+      .extract("video1")
+      .await
+      .unwrap();
+
+    let Extraction::Proxy { url, .. } = extraction else {
+      panic!("expected a proxied stream");
+    };
+    assert_eq!(url, instance.absolute_url("/videoplayback?itag=140"));
+  }
+
+  #[test]
+  fn test_parse_instances() {
+    let json = r#"[
+      ["inv.example.com", {"uri": "https://inv.example.com", "region": "DE", "type": "https", "api": true}],
+      ["noapi.example.com", {"uri": "https://noapi.example.com", "region": "US", "type": "https", "api": false}],
+      ["example.onion", {"uri": "http://example.onion", "region": null, "type": "onion", "api": null}]
+    ]"#;
+
+    let instances = parse_instances(json).unwrap();
+    assert_eq!(instances.len(), 1);
+    assert_eq!(instances[0].instance.api_url, "https://inv.example.com");
+  }
+}
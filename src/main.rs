@@ -1,26 +1,47 @@
 use std::{
   net::SocketAddr,
   sync::{Arc, LazyLock},
+  time::Duration,
 };
 
 use axum::{
-  headers::ContentType, response::IntoResponse, routing::get, Extension,
-  Router, TypedHeader,
+  headers::ContentType, middleware, response::IntoResponse, routing::get,
+  Extension, Router, TypedHeader,
 };
 
+mod admin;
 mod audio;
+mod audio_options;
+mod audio_signature;
 mod audio_store;
+mod channel_filter;
+mod chapters;
+mod config;
 mod error;
 mod extractor;
 mod feed;
+mod feed_cache;
 mod harvestor;
+mod health;
+mod invidious;
+mod metrics;
 mod piped;
 mod podcast;
+mod prefetch;
+mod probe;
+mod rate_limit;
 mod rss;
+mod sponsorblock;
+mod subscriber;
+mod transcript;
 mod util;
+mod video_info;
 
 pub use error::{Error, Result};
-use tracing::info;
+use tokio_graceful_shutdown::{
+  FutureExt as _, SubsystemBuilder, SubsystemHandle, Toplevel,
+};
+use tracing::{info, warn};
 pub use util::W;
 pub use util::YTDLP_MUTEX;
 
@@ -42,41 +63,121 @@ pub static AUDIO_STORE_PATH: LazyLock<String> = LazyLock::new(|| {
     .unwrap_or_else(|_| "/tmp/audio-store".to_owned())
 });
 
+// persistent state other than the audio files, e.g. episode dates
+pub static DATA_DIR: LazyLock<String> = LazyLock::new(|| {
+  std::env::var("DATA_DIR").unwrap_or_else(|_| "/tmp/data".to_owned())
+});
+
 pub static BIND_ADDRESS: LazyLock<SocketAddr> = LazyLock::new(|| {
   std::env::var("BIND_ADDRESS")
     .map(|addr| addr.parse().expect("Invalid BIND_ADDRESS"))
     .unwrap_or_else(|_| SocketAddr::from(([0, 0, 0, 0], 8080)))
 });
 
+// seconds to finish in-flight requests and downloads on shutdown
+static SHUTDOWN_TIMEOUT: LazyLock<Duration> = LazyLock::new(|| {
+  let secs = std::env::var("SHUTDOWN_TIMEOUT")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(25);
+  Duration::from_secs(secs)
+});
+
 #[tokio::main(worker_threads = 4)]
 async fn main() -> Result<()> {
   tracing_subscriber::fmt::init();
 
-  #[cfg(unix)]
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  if let Some(("subscriber", args)) =
+    args.split_first().map(|(cmd, args)| (cmd.as_str(), args))
   {
-    tokio::spawn(async {
-      signal_handler().await.expect("Signal handler failed");
-    });
+    return subscriber::cli(args);
   }
 
+  harvestor::load_episode_dates();
+  // fail early on an invalid config file
+  LazyLock::force(&config::CONFIG);
+
   let audio_store = AudioStore::new(AUDIO_STORE_PATH.as_str());
-  let audio_store_ref = audio_store.spawn();
+  let audio_store_ref = Arc::new(audio_store.spawn());
 
   let app = Router::new()
     .route("/", get(homepage))
     .route("/health", get(health))
     .route("/get-podcast", get(feed::channel_podcast_url))
     .route("/channel/:channel_id", get(feed::channel_podcast_xml))
+    .route("/playlist/:playlist_id", get(feed::playlist_podcast_xml))
+    .route("/bundle", get(feed::bundle_podcast_xml))
     .route("/audio/:video_id", get(audio::get_audio))
-    .layer(Extension(Arc::new(audio_store_ref)));
+    .route("/chapters/:video_id", get(chapters::get_chapters))
+    .route("/transcript/:video_id", get(transcript::get_transcript))
+    .route("/metrics", get(metrics::get_metrics))
+    .nest("/admin", admin::router())
+    .route_layer(middleware::from_fn(metrics::track_request))
+    .layer(Extension(audio_store_ref.clone()));
 
   info!("Listening on {}", *BIND_ADDRESS);
   info!("Public URL: {}", &*INSTANCE_PUBLIC_URL);
 
-  tokio::task::spawn(async move { piped::PipedInstanceRepo::run().await });
+  // on SIGINT or SIGTERM: stop accepting requests, let the in-flight
+  // ones and the yt-dlp downloads finish, and stop the background jobs
+  let shutdown = Toplevel::new(move |s| async move {
+    s.start(SubsystemBuilder::new("http", |s| serve(s, app)));
+    s.start(SubsystemBuilder::new("ytdlp", util::drain_ytdlp_jobs));
+    s.start(SubsystemBuilder::new("piped", |s| async move {
+      piped::PipedInstanceRepo::run()
+        .cancel_on_shutdown(&s)
+        .await
+        .ok();
+      Ok::<_, Error>(())
+    }));
+    if config::CONFIG.uses_invidious() {
+      s.start(SubsystemBuilder::new("invidious", |s| async move {
+        let run = invidious::InvidiousInstanceRepo::run();
+        run.cancel_on_shutdown(&s).await.ok();
+        Ok::<_, Error>(())
+      }));
+    }
+    let probe_audio_store = audio_store_ref.clone();
+    s.start(SubsystemBuilder::new("probe", |s| async move {
+      probe::run(probe_audio_store)
+        .cancel_on_shutdown(&s)
+        .await
+        .ok();
+      Ok::<_, Error>(())
+    }));
+    s.start(SubsystemBuilder::new("prefetch", |s| async move {
+      prefetch::run(audio_store_ref)
+        .cancel_on_shutdown(&s)
+        .await
+        .ok();
+      Ok::<_, Error>(())
+    }));
+  })
+  .catch_signals()
+  .handle_shutdown_requests(*SHUTDOWN_TIMEOUT)
+  .await;
+
+  if let Err(e) = shutdown {
+    warn!("shutdown did not finish cleanly: {e}");
+  }
+
+  // downloads still running after the timeout are killed when the
+  // runtime drops them. their partial files are removed on the next
+  // start, deleting them now could race a download about to finish.
+  if util::work_in_progress() == 0 {
+    audio_store::remove_partial_files(AUDIO_STORE_PATH.as_str());
+  }
+  harvestor::flush_episode_dates();
+  info!("shut down");
+
+  Ok(())
+}
 
+async fn serve(subsys: SubsystemHandle, app: Router) -> Result<()> {
   axum::Server::bind(&BIND_ADDRESS)
-    .serve(app.into_make_service())
+    .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+    .with_graceful_shutdown(subsys.on_shutdown_requested())
     .await
     .expect("Failed to start server");
 
@@ -95,22 +196,3 @@ async fn homepage() -> impl IntoResponse {
 async fn health() -> impl IntoResponse {
   "ok".to_owned()
 }
-
-#[cfg(unix)]
-async fn signal_handler() -> Result<()> {
-  use tokio::signal::unix::{signal, SignalKind};
-
-  let mut sigint = signal(SignalKind::interrupt())?;
-  let mut sigterm = signal(SignalKind::terminate())?;
-
-  tokio::select! {
-    _ = sigint.recv() => {
-      info!("Received SIGINT, shutting down...");
-    }
-    _ = sigterm.recv() => {
-      info!("Received SIGTERM, shutting down...");
-    }
-  };
-
-  std::process::exit(0)
-}
@@ -0,0 +1,208 @@
+use std::{
+  collections::BTreeMap,
+  fmt::Write as _,
+  sync::{LazyLock, Mutex},
+  time::{Duration, Instant},
+};
+
+use axum::{
+  extract::MatchedPath,
+  http::Request,
+  middleware::Next,
+  response::{IntoResponse, Response},
+};
+use reqwest::header;
+
+// in seconds. downloads take minutes, so the default prometheus buckets
+// are extended.
+const BUCKETS: &[f64] = &[
+  0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+  120.0, 300.0,
+];
+
+pub static METRICS: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+// labels rendered as `name="value",...`
+type Key = (&'static str, String);
+
+#[derive(Default)]
+struct Histogram {
+  counts: Vec<u64>,
+  sum: f64,
+  count: u64,
+}
+
+#[derive(Default)]
+pub struct Registry {
+  counters: Mutex<BTreeMap<Key, u64>>,
+  gauges: Mutex<BTreeMap<Key, f64>>,
+  histograms: Mutex<BTreeMap<Key, Histogram>>,
+}
+
+impl Registry {
+  pub fn inc(&self, name: &'static str, labels: &[(&str, &str)]) {
+    let key = (name, render_labels(labels));
+    *self.counters.lock().unwrap().entry(key).or_default() += 1;
+  }
+
+  pub fn set(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let key = (name, render_labels(labels));
+    self.gauges.lock().unwrap().insert(key, value);
+  }
+
+  pub fn add(&self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let key = (name, render_labels(labels));
+    *self.gauges.lock().unwrap().entry(key).or_default() += value;
+  }
+
+  // drop all label sets of a gauge, e.g. when the labels change
+  pub fn clear(&self, name: &'static str) {
+    self.gauges.lock().unwrap().retain(|(n, _), _| *n != name);
+  }
+
+  pub fn observe(
+    &self,
+    name: &'static str,
+    labels: &[(&str, &str)],
+    value: Duration,
+  ) {
+    let value = value.as_secs_f64();
+    let key = (name, render_labels(labels));
+    let mut histograms = self.histograms.lock().unwrap();
+    let histogram = histograms.entry(key).or_default();
+
+    histogram.counts.resize(BUCKETS.len(), 0);
+    for (count, bound) in histogram.counts.iter_mut().zip(BUCKETS) {
+      if value <= *bound {
+        *count += 1;
+      }
+    }
+    histogram.sum += value;
+    histogram.count += 1;
+  }
+
+  // the prometheus text exposition format
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+
+    let counters = self.counters.lock().unwrap();
+    render_family(&mut out, "counter", counters.iter(), |out, n, l, v| {
+      writeln!(out, "{n}{} {v}", braced(l)).unwrap();
+    });
+
+    let gauges = self.gauges.lock().unwrap();
+    render_family(&mut out, "gauge", gauges.iter(), |out, n, l, v| {
+      writeln!(out, "{n}{} {v}", braced(l)).unwrap();
+    });
+
+    let histograms = self.histograms.lock().unwrap();
+    render_family(&mut out, "histogram", histograms.iter(), |out, n, l, h| {
+      let sep = if l.is_empty() { "" } else { "," };
+      for (count, bound) in h.counts.iter().zip(BUCKETS) {
+        writeln!(out, "{n}_bucket{{{l}{sep}le=\"{bound}\"}} {count}").unwrap();
+      }
+      let count = h.count;
+      writeln!(out, "{n}_bucket{{{l}{sep}le=\"+Inf\"}} {count}").unwrap();
+      writeln!(out, "{n}_sum{} {}", braced(l), h.sum).unwrap();
+      writeln!(out, "{n}_count{} {count}", braced(l)).unwrap();
+    });
+
+    out
+  }
+}
+
+// write the samples, with a type line before each metric
+fn render_family<'a, T: 'a>(
+  out: &mut String,
+  kind: &str,
+  samples: impl Iterator<Item = (&'a Key, &'a T)>,
+  mut render: impl FnMut(&mut String, &str, &str, &T),
+) {
+  let mut last_name = None;
+  for ((name, labels), value) in samples {
+    if last_name != Some(name) {
+      writeln!(out, "# TYPE {name} {kind}").unwrap();
+      last_name = Some(name);
+    }
+    render(out, name, labels, value);
+  }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+  labels
+    .iter()
+    .map(|(name, value)| {
+      let value = value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n");
+      format!("{name}=\"{value}\"")
+    })
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+fn braced(labels: &str) -> String {
+  if labels.is_empty() {
+    String::new()
+  } else {
+    format!("{{{labels}}}")
+  }
+}
+
+pub async fn get_metrics() -> impl IntoResponse {
+  let content_type = "text/plain; version=0.0.4";
+  ([(header::CONTENT_TYPE, content_type)], METRICS.render())
+}
+
+// count the requests and their latency per route
+pub async fn track_request<B>(req: Request<B>, next: Next<B>) -> Response {
+  let route = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map(|path| path.as_str().to_string())
+    .unwrap_or_default();
+
+  let start = Instant::now();
+  let response = next.run(req).await;
+
+  let status = response.status().as_u16().to_string();
+  METRICS.inc(
+    "http_requests_total",
+    &[("route", &route), ("status", &status)],
+  );
+  METRICS.observe(
+    "http_request_duration_seconds",
+    &[("route", &route)],
+    start.elapsed(),
+  );
+
+  response
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_render() {
+    let registry = Registry::default();
+    registry.inc("requests_total", &[("route", "/a\"b")]);
+    registry.inc("requests_total", &[("route", "/a\"b")]);
+    registry.set("queue_depth", &[], 3.0);
+    registry.observe("wait_seconds", &[("kind", "x")], Duration::from_secs(2));
+
+    let output = registry.render();
+    let lines: Vec<_> = output.lines().collect();
+
+    assert!(lines.contains(&"# TYPE requests_total counter"));
+    assert!(lines.contains(&r#"requests_total{route="/a\"b"} 2"#));
+    assert!(lines.contains(&"# TYPE queue_depth gauge"));
+    assert!(lines.contains(&"queue_depth 3"));
+    assert!(lines.contains(&"# TYPE wait_seconds histogram"));
+    assert!(lines.contains(&r#"wait_seconds_bucket{kind="x",le="1"} 0"#));
+    assert!(lines.contains(&r#"wait_seconds_bucket{kind="x",le="2.5"} 1"#));
+    assert!(lines.contains(&r#"wait_seconds_bucket{kind="x",le="+Inf"} 1"#));
+    assert!(lines.contains(&r#"wait_seconds_count{kind="x"} 1"#));
+  }
+}
@@ -15,6 +15,8 @@ use tokio::{
 };
 use tracing::{info, warn};
 
+use crate::metrics::METRICS;
+
 const DEFAULT_PIPED_INSTANCE: &str = "https://pipedapi.leptons.xyz";
 
 const PIPED_WIKI_URL: &str =
@@ -78,7 +80,7 @@ where
 }
 
 #[derive(Debug, Clone, Serialize)]
-struct PipedInstanceStat {
+pub struct PipedInstanceStat {
   instance: PipedInstance,
   name: String,
   countries: Vec<String>,
@@ -88,6 +90,8 @@ struct PipedInstanceStat {
 pub struct PipedInstanceRepo {
   wiki_url: String,
   current_instance: Mutex<PipedInstance>,
+  // the instances probed last time
+  candidates: Mutex<Vec<PipedInstanceStat>>,
   update_signal: Sender<()>,
   update_receiver: RwLock<Option<Receiver<()>>>,
   interval: Duration,
@@ -111,12 +115,20 @@ impl PipedInstanceRepo {
     GLOBAL_REPO.current_instance.lock().unwrap().clone()
   }
 
+  pub fn candidates() -> Vec<PipedInstanceStat> {
+    GLOBAL_REPO.candidates.lock().unwrap().clone()
+  }
+
   pub fn notify_update<E: std::error::Error>(e: E) -> E {
     eprintln!("Failed requesting piped: {e:?}, refreshing");
-    GLOBAL_REPO.update_signal.try_send(()).ok();
+    Self::request_update();
     e
   }
 
+  pub fn request_update() {
+    GLOBAL_REPO.update_signal.try_send(()).ok();
+  }
+
   fn new(interval: Duration) -> Self {
     let (update_signal, update_receiver) = channel(1);
     let update_receiver = RwLock::new(Some(update_receiver));
@@ -126,6 +138,7 @@ impl PipedInstanceRepo {
       current_instance: Mutex::new(PipedInstance::new(
         DEFAULT_PIPED_INSTANCE.to_string(),
       )),
+      candidates: Mutex::new(vec![]),
       update_signal,
       update_receiver,
       interval,
@@ -152,6 +165,7 @@ impl PipedInstanceRepo {
         continue;
       };
       let instances = check_latency(&instances).await;
+      *this.candidates.lock().unwrap() = instances.clone();
 
       if instances.is_empty() {
         warn!(
@@ -166,6 +180,13 @@ impl PipedInstanceRepo {
         let mut global = this.current_instance.lock().unwrap();
         *global = instance.instance;
         info!("Selected new piped instance: {}", &global.api_url);
+
+        METRICS.clear("piped_instance_latency_seconds");
+        if let Some(latency) = instance.latency {
+          let labels = [("instance", global.api_url.as_str())];
+          let latency = latency as f64 / 1000.0;
+          METRICS.set("piped_instance_latency_seconds", &labels, latency);
+        }
       };
 
       tokio::select! {
@@ -278,7 +299,7 @@ mod tests {
   async fn test_piped_instance_repo() {
     let repo = PipedInstanceRepo::global();
 
-    let instances = repo.pull_latest().await?;
+    let instances = repo.pull_latest().await.unwrap();
     println!("{:#?}", &instances);
     assert!(!instances.is_empty());
   }
@@ -1,6 +1,29 @@
-use crate::GENERATOR_STR;
+mod atom;
+mod json_feed;
 
-#[derive(Debug, Default)]
+use std::{
+  cmp::Reverse,
+  collections::{BTreeMap, HashSet},
+};
+
+use chrono::{DateTime, Utc};
+
+use http_types::Url;
+use rss::extension::{Extension, ExtensionMap};
+
+use crate::{
+  audio_options::{AudioFormat, AudioOptions},
+  audio_signature,
+  subscriber::Access,
+  transcript::{TranscriptFormat, TRANSCRIPT_LANG},
+  GENERATOR_STR, INSTANCE_PUBLIC_URL,
+};
+
+pub use json_feed::JsonFeed;
+
+const PODCAST_NAMESPACE: &str = "https://podcastindex.org/namespace/1.0";
+
+#[derive(Debug, Default, Clone)]
 pub struct Podcast {
   pub title: String,
   pub description: String,
@@ -13,6 +36,94 @@ pub struct Podcast {
   pub episodes: Vec<Episode>,
 }
 
+impl Podcast {
+  // merge several podcasts into one, newest episodes first
+  pub fn bundle(
+    podcasts: Vec<Podcast>,
+    channel_url: String,
+    title: Option<String>,
+    logo_url: Option<String>,
+  ) -> Self {
+    let titles: Vec<_> = podcasts.iter().map(|p| p.title.as_str()).collect();
+    let title = title.unwrap_or_else(|| titles.join(" + "));
+    let description = format!("Bundle of {}", titles.join(", "));
+    let logo_url = logo_url
+      .or_else(|| podcasts.first().map(|p| p.logo_url.clone()))
+      .unwrap_or_default();
+
+    let mut authors: Vec<_> = vec![];
+    let mut categories = vec![];
+    let mut languages = HashSet::new();
+    for podcast in &podcasts {
+      if !authors.contains(&podcast.author) {
+        authors.push(podcast.author.clone());
+      }
+      for category in &podcast.categories {
+        if !categories.contains(category) {
+          categories.push(category.clone());
+        }
+      }
+      languages.insert(podcast.language.clone());
+    }
+    // only claim a language if every source agrees on it
+    let language = match languages.len() {
+      1 => languages.into_iter().next().unwrap(),
+      _ => String::new(),
+    };
+
+    let mut seen = HashSet::new();
+    let mut episodes: Vec<_> = podcasts
+      .into_iter()
+      .flat_map(|p| p.episodes)
+      .filter(|e| seen.insert(e.video_id.clone()))
+      .collect();
+    episodes.sort_by_cached_key(|e| {
+      Reverse(DateTime::parse_from_rfc2822(&e.pub_date).ok())
+    });
+
+    let last_build_date = episodes
+      .first()
+      .map(|e| e.pub_date.clone())
+      .unwrap_or_else(|| Utc::now().to_rfc2822());
+
+    Self {
+      title,
+      description,
+      last_build_date,
+      language,
+      author: authors.join(", "),
+      logo_url,
+      categories,
+      channel_url,
+      episodes,
+    }
+  }
+
+  pub fn set_audio_options(&mut self, options: &AudioOptions) {
+    for episode in &mut self.episodes {
+      episode.audio_info.set_options(options);
+    }
+  }
+
+  // let the subscriber fetch the audio, chapters and transcripts of the
+  // episodes
+  pub fn set_access(&mut self, access: &Access) {
+    for episode in &mut self.episodes {
+      let Some(grant) = access.audio_grant(&episode.video_id) else {
+        continue;
+      };
+      let urls = [
+        episode.chapters_url.as_mut(),
+        episode.transcript_url.as_mut(),
+      ];
+      for url in urls.into_iter().flatten() {
+        add_query_pair(url, grant.clone());
+      }
+      episode.audio_info.add_query_pair(grant);
+    }
+  }
+}
+
 impl From<Podcast> for rss::Channel {
   fn from(podcast: Podcast) -> Self {
     let itunes_categoris = podcast.categories.into_iter().map(|c| {
@@ -37,6 +148,9 @@ impl From<Podcast> for rss::Channel {
       .itunes_ext(Some(itunes_ext))
       .generator(Some(GENERATOR_STR.to_string()))
       .build();
+    channel
+      .namespaces
+      .insert("podcast".to_string(), PODCAST_NAMESPACE.to_string());
 
     for episode in podcast.episodes {
       channel.items.push(episode.into());
@@ -46,21 +160,52 @@ impl From<Podcast> for rss::Channel {
   }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Thumbnail {
   pub url: String,
   pub width: u32,
   pub height: u32,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct AudioInfo {
   pub url: String,
   pub mime_type: String,
 }
 
-#[derive(Debug, Default)]
+impl AudioInfo {
+  pub fn for_video(video_id: &str) -> Self {
+    let mut audio_info = Self {
+      url: format!("{}/audio/{}", &*INSTANCE_PUBLIC_URL, video_id),
+      mime_type: AudioFormat::default().mime_type().to_string(),
+    };
+    if let Some(signature) = audio_signature::sign(video_id) {
+      audio_info.add_query_pair(signature);
+    }
+    audio_info
+  }
+
+  fn set_options(&mut self, options: &AudioOptions) {
+    self.mime_type = options.audio_format.mime_type().to_string();
+    let query_pairs = options.query_pairs();
+    if query_pairs.is_empty() {
+      return;
+    }
+
+    if let Ok(mut url) = Url::parse(&self.url) {
+      url.query_pairs_mut().extend_pairs(query_pairs);
+      self.url = url.into();
+    }
+  }
+
+  fn add_query_pair(&mut self, pair: (&str, String)) {
+    add_query_pair(&mut self.url, pair);
+  }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Episode {
+  pub video_id: String,
   pub title: String,
   pub link: String,
   pub description: String,
@@ -70,6 +215,10 @@ pub struct Episode {
   pub duration: u64,
   pub thumbnail: Thumbnail,
   pub audio_info: AudioInfo,
+  pub chapters_url: Option<String>,
+  pub transcript_url: Option<String>,
+  // the channel of the video, when the feed mixes channels
+  pub channel_id: Option<String>,
 }
 
 impl From<Episode> for rss::Item {
@@ -102,6 +251,38 @@ impl From<Episode> for rss::Item {
       )
       .build();
 
+    let mut extensions = ExtensionMap::new();
+    if let Some(url) = episode.chapters_url {
+      let chapters = podcast_extension(
+        "chapters",
+        [("url", url), ("type", "application/json+chapters".into())],
+      );
+      extensions
+        .entry("podcast".to_string())
+        .or_default()
+        .insert("chapters".to_string(), vec![chapters]);
+    }
+    if let Some(url) = episode.transcript_url {
+      let transcripts = [TranscriptFormat::Vtt, TranscriptFormat::Srt]
+        .into_iter()
+        .map(|format| {
+          podcast_extension(
+            "transcript",
+            [
+              ("url", transcript_format_url(&url, format)),
+              ("type", format.mime_type().to_string()),
+              ("language", TRANSCRIPT_LANG.clone()),
+              ("rel", "captions".to_string()),
+            ],
+          )
+        })
+        .collect();
+      extensions
+        .entry("podcast".to_string())
+        .or_default()
+        .insert("transcript".to_string(), transcripts);
+    }
+
     rss::Item {
       title: Some(episode.title),
       link: Some(episode.link),
@@ -113,11 +294,46 @@ impl From<Episode> for rss::Item {
       description: Some(description_html),
       itunes_ext: Some(itunes),
       enclosure: Some(enclosure),
+      extensions,
       ..Default::default()
     }
   }
 }
 
+fn add_query_pair(url: &mut String, (name, value): (&str, String)) {
+  if let Ok(mut parsed) = Url::parse(url) {
+    parsed.query_pairs_mut().append_pair(name, &value);
+    *url = parsed.into();
+  }
+}
+
+fn transcript_format_url(url: &str, format: TranscriptFormat) -> String {
+  if format == TranscriptFormat::default() {
+    return url.to_string();
+  }
+
+  let Ok(mut url) = Url::parse(url) else {
+    return url.to_string();
+  };
+  url.query_pairs_mut().append_pair("format", format.name());
+  url.into()
+}
+
+// an attribute-only element in the podcasting 2.0 namespace
+fn podcast_extension<const N: usize>(
+  name: &str,
+  attrs: [(&str, String); N],
+) -> Extension {
+  Extension {
+    name: format!("podcast:{name}"),
+    attrs: attrs
+      .into_iter()
+      .map(|(k, v)| (k.to_string(), v))
+      .collect::<BTreeMap<_, _>>(),
+    ..Default::default()
+  }
+}
+
 fn seconds_to_duration(secs: u64) -> String {
   let hours = secs / 3600;
   let minutes = (secs % 3600) / 60;
@@ -129,3 +345,71 @@ fn seconds_to_duration(secs: u64) -> String {
     format!("{:02}:{:02}", minutes, seconds)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_audio_info_options() {
+    let mut audio_info = AudioInfo::for_video("abc");
+    audio_info.set_options(&AudioOptions::default());
+    assert!(audio_info.url.ends_with("/audio/abc"));
+    assert_eq!(audio_info.mime_type, "audio/mp4");
+
+    let mut audio_info = AudioInfo::for_video("abc");
+    audio_info.set_options(&AudioOptions {
+      audio_format: AudioFormat::Mp3,
+      sponsorblock: Some("sponsor,selfpromo".parse().unwrap()),
+    });
+    assert!(audio_info.url.ends_with(
+      "/audio/abc?audio_format=mp3&sponsorblock=selfpromo%2Csponsor"
+    ));
+    assert_eq!(audio_info.mime_type, "audio/mpeg");
+  }
+
+  #[test]
+  fn test_transcript_format_url() {
+    let url = "http://localhost/transcript/abc?sig=x";
+    assert_eq!(transcript_format_url(url, TranscriptFormat::Vtt), url);
+    assert_eq!(
+      transcript_format_url(url, TranscriptFormat::Srt),
+      "http://localhost/transcript/abc?sig=x&format=srt"
+    );
+  }
+
+  #[test]
+  fn test_bundle() {
+    let episode = |id: &str, pub_date: &str| Episode {
+      video_id: id.to_string(),
+      guid: id.to_string(),
+      pub_date: pub_date.to_string(),
+      audio_info: AudioInfo::for_video(id),
+      ..Default::default()
+    };
+    let a = Podcast {
+      title: "A".into(),
+      episodes: vec![
+        episode("a2", "Tue, 02 Jan 2024 00:00:00 +0000"),
+        episode("a1", "Mon, 01 Jan 2024 00:00:00 +0000"),
+      ],
+      ..Default::default()
+    };
+    let b = Podcast {
+      title: "B".into(),
+      episodes: vec![
+        episode("b1", "Mon, 01 Jan 2024 12:00:00 +0000"),
+        episode("a2", "Tue, 02 Jan 2024 00:00:00 +0000"),
+      ],
+      ..Default::default()
+    };
+
+    let url = "http://localhost/bundle?channels=A%2CB".to_string();
+    let bundle = Podcast::bundle(vec![a, b], url.clone(), None, None);
+    let ids: Vec<_> = bundle.episodes.iter().map(|e| &e.guid).collect();
+    assert_eq!(bundle.title, "A + B");
+    assert_eq!(bundle.channel_url, url);
+    assert_eq!(ids, ["a2", "b1", "a1"]);
+    assert_eq!(bundle.last_build_date, "Tue, 02 Jan 2024 00:00:00 +0000");
+  }
+}
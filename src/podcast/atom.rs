@@ -0,0 +1,143 @@
+use atom_syndication::{
+  Category, Entry, Feed, FixedDateTime, Generator, Link, Person,
+};
+use chrono::{DateTime, TimeZone as _, Utc};
+
+use super::{Episode, Podcast};
+use crate::GENERATOR_STR;
+
+impl From<Podcast> for Feed {
+  fn from(podcast: Podcast) -> Self {
+    // a feed without a build date was updated with its newest episode
+    let updated = parse_date(&podcast.last_build_date)
+      .or_else(|| {
+        let dates = podcast.episodes.iter().map(|e| parse_date(&e.pub_date));
+        dates.flatten().max()
+      })
+      .unwrap_or_else(epoch);
+    let categories = podcast.categories.into_iter().map(|c| Category {
+      term: c,
+      ..Default::default()
+    });
+    let generator = Generator {
+      value: GENERATOR_STR.to_string(),
+      ..Default::default()
+    };
+
+    Feed {
+      id: podcast.channel_url.clone(),
+      title: podcast.title.into(),
+      subtitle: Some(podcast.description.into()),
+      updated,
+      authors: people(podcast.author),
+      categories: categories.collect(),
+      generator: Some(generator),
+      logo: Some(podcast.logo_url.clone()),
+      icon: Some(podcast.logo_url),
+      links: vec![link("alternate", podcast.channel_url, None)],
+      lang: (!podcast.language.is_empty()).then_some(podcast.language),
+      entries: podcast.episodes.into_iter().map(Into::into).collect(),
+      ..Default::default()
+    }
+  }
+}
+
+impl From<Episode> for Entry {
+  fn from(episode: Episode) -> Self {
+    let pub_date = parse_date(&episode.pub_date).unwrap_or_else(epoch);
+    let enclosure = link(
+      "enclosure",
+      episode.audio_info.url,
+      Some(episode.audio_info.mime_type),
+    );
+
+    Entry {
+      id: episode.guid,
+      title: episode.title.into(),
+      updated: pub_date,
+      published: Some(pub_date),
+      authors: people(episode.author),
+      summary: Some(episode.description.into()),
+      links: vec![link("alternate", episode.link, None), enclosure],
+      ..Default::default()
+    }
+  }
+}
+
+// atom wants a name for every author, none is better than an empty one
+fn people(name: String) -> Vec<Person> {
+  if name.is_empty() {
+    return vec![];
+  }
+  vec![Person {
+    name,
+    ..Default::default()
+  }]
+}
+
+fn link(rel: &str, href: String, mime_type: Option<String>) -> Link {
+  Link {
+    rel: rel.to_string(),
+    href,
+    mime_type,
+    ..Default::default()
+  }
+}
+
+// the podcast model keeps rfc2822 dates for rss
+fn parse_date(date: &str) -> Option<FixedDateTime> {
+  DateTime::parse_from_rfc2822(date).ok()
+}
+
+// for the dates that can't be parsed, so the feed and its etag stay the
+// same between requests
+fn epoch() -> FixedDateTime {
+  Utc.timestamp_opt(0, 0).unwrap().fixed_offset()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::podcast::AudioInfo;
+
+  #[test]
+  fn test_from_podcast() {
+    let episode = |id: &str, pub_date: &str, author: &str| Episode {
+      video_id: id.to_string(),
+      guid: id.to_string(),
+      title: id.to_uppercase(),
+      pub_date: pub_date.to_string(),
+      author: author.to_string(),
+      audio_info: AudioInfo::for_video(id),
+      ..Default::default()
+    };
+    let podcast = Podcast {
+      title: "Podcast".into(),
+      author: "".into(),
+      language: "en".into(),
+      channel_url: "https://www.youtube.com/channel/UCa".into(),
+      episodes: vec![
+        episode("a2", "Tue, 02 Jan 2024 00:00:00 +0000", "Alice"),
+        episode("a1", "not a date", ""),
+      ],
+      ..Default::default()
+    };
+
+    let feed: Feed = podcast.into();
+    assert_eq!(feed.id, "https://www.youtube.com/channel/UCa");
+    assert!(feed.authors.is_empty());
+    assert_eq!(feed.lang.as_deref(), Some("en"));
+    // the build date is missing, so the newest episode's
+    assert_eq!(feed.updated.to_rfc3339(), "2024-01-02T00:00:00+00:00");
+
+    let [a2, a1] = &feed.entries[..] else {
+      panic!("expected two entries");
+    };
+    assert_eq!(a2.title.as_str(), "A2");
+    assert_eq!(a2.authors[0].name, "Alice");
+    assert_eq!(a2.links[1].rel, "enclosure");
+    assert!(a2.links[1].href.contains("/audio/a2"));
+    assert!(a1.authors.is_empty());
+    assert_eq!(a1.updated, epoch());
+  }
+}
@@ -0,0 +1,144 @@
+use chrono::DateTime;
+use serde::Serialize;
+
+use super::{Episode, Podcast};
+
+const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+// https://www.jsonfeed.org/version/1.1/
+#[derive(Debug, Serialize)]
+pub struct JsonFeed {
+  version: &'static str,
+  title: String,
+  home_page_url: String,
+  description: String,
+  icon: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  language: Option<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  authors: Vec<Author>,
+  items: Vec<Item>,
+}
+
+#[derive(Debug, Serialize)]
+struct Author {
+  name: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Item {
+  id: String,
+  url: String,
+  title: String,
+  content_text: String,
+  image: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  date_published: Option<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  authors: Vec<Author>,
+  attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Serialize)]
+struct Attachment {
+  url: String,
+  mime_type: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  duration_in_seconds: Option<u64>,
+}
+
+// none rather than an author without a name
+fn authors(name: String) -> Vec<Author> {
+  if name.is_empty() {
+    return vec![];
+  }
+  vec![Author { name }]
+}
+
+impl From<Podcast> for JsonFeed {
+  fn from(podcast: Podcast) -> Self {
+    Self {
+      version: JSON_FEED_VERSION,
+      title: podcast.title,
+      home_page_url: podcast.channel_url,
+      description: podcast.description,
+      icon: podcast.logo_url,
+      language: (!podcast.language.is_empty()).then_some(podcast.language),
+      authors: authors(podcast.author),
+      items: podcast.episodes.into_iter().map(Into::into).collect(),
+    }
+  }
+}
+
+impl From<Episode> for Item {
+  fn from(episode: Episode) -> Self {
+    let attachment = Attachment {
+      url: episode.audio_info.url,
+      mime_type: episode.audio_info.mime_type,
+      duration_in_seconds: (episode.duration > 0).then_some(episode.duration),
+    };
+
+    Self {
+      id: episode.guid,
+      url: episode.link,
+      title: episode.title,
+      content_text: episode.description,
+      image: episode.thumbnail.url,
+      date_published: DateTime::parse_from_rfc2822(&episode.pub_date)
+        .ok()
+        .map(|d| d.to_rfc3339()),
+      authors: authors(episode.author),
+      attachments: vec![attachment],
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use serde_json::json;
+
+  use super::*;
+  use crate::podcast::AudioInfo;
+
+  #[test]
+  fn test_from_podcast() {
+    let podcast = Podcast {
+      title: "Podcast".into(),
+      author: "".into(),
+      channel_url: "https://www.youtube.com/channel/UCa".into(),
+      episodes: vec![Episode {
+        guid: "a1".into(),
+        title: "A1".into(),
+        link: "https://www.youtube.com/watch?v=a1".into(),
+        pub_date: "Tue, 02 Jan 2024 00:00:00 +0000".into(),
+        author: "Alice".into(),
+        duration: 60,
+        audio_info: AudioInfo {
+          url: "http://localhost/audio/a1".into(),
+          mime_type: "audio/mp4".into(),
+        },
+        ..Default::default()
+      }],
+      ..Default::default()
+    };
+
+    let feed = serde_json::to_value(JsonFeed::from(podcast)).unwrap();
+    assert_eq!(feed["version"], JSON_FEED_VERSION);
+    assert_eq!(feed["home_page_url"], "https://www.youtube.com/channel/UCa");
+    assert!(feed.get("authors").is_none());
+    assert!(feed.get("language").is_none());
+
+    let item = &feed["items"][0];
+    assert_eq!(item["id"], "a1");
+    assert_eq!(item["date_published"], "2024-01-02T00:00:00+00:00");
+    assert_eq!(item["authors"], json!([{ "name": "Alice" }]));
+    assert_eq!(
+      item["attachments"],
+      json!([{
+        "url": "http://localhost/audio/a1",
+        "mime_type": "audio/mp4",
+        "duration_in_seconds": 60,
+      }])
+    );
+  }
+}
@@ -0,0 +1,199 @@
+use std::{
+  sync::{Arc, LazyLock, Mutex},
+  time::Duration,
+};
+
+use lru_time_cache::LruCache;
+use tracing::{info, warn};
+
+use crate::{
+  audio_options::AudioFormat, audio_store::AudioStoreRef, channel_filter,
+  extractor::YtdlpFile, feed, YTDLP_MUTEX,
+};
+
+// seconds between two prefetch rounds, prefetching is off when unset
+static PREFETCH_INTERVAL: LazyLock<Option<Duration>> = LazyLock::new(|| {
+  std::env::var("PREFETCH_INTERVAL")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .filter(|&secs| secs > 0)
+    .map(Duration::from_secs)
+});
+
+// how many of the newest episodes of a channel to prefetch
+static PREFETCH_EPISODES: LazyLock<usize> = LazyLock::new(|| {
+  std::env::var("PREFETCH_EPISODES")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(3)
+});
+
+// comma-separated channel ids that are always prefetched
+static PREFETCH_CHANNELS: LazyLock<Vec<String>> = LazyLock::new(|| {
+  std::env::var("PREFETCH_CHANNELS")
+    .unwrap_or_default()
+    .split(',')
+    .map(str::trim)
+    .filter(|id| !id.is_empty())
+    .map(str::to_string)
+    .collect()
+});
+
+// channels whose feed was requested in the last day
+static RECENT_CHANNELS: LazyLock<Mutex<LruCache<String, ()>>> =
+  LazyLock::new(|| {
+    Mutex::new(LruCache::with_expiry_duration_and_capacity(
+      Duration::from_secs(24 * 60 * 60),
+      50,
+    ))
+  });
+
+pub fn touch_channel(channel_id: &str) {
+  if PREFETCH_INTERVAL.is_some() {
+    RECENT_CHANNELS
+      .lock()
+      .unwrap()
+      .insert(channel_id.to_string(), ());
+  }
+}
+
+fn channels() -> Vec<String> {
+  let recent = RECENT_CHANNELS.lock().unwrap();
+  merge_channels(&PREFETCH_CHANNELS, recent.peek_iter().map(|(id, _)| id))
+}
+
+// the configured channels first, then the recent ones
+fn merge_channels<'a>(
+  configured: &[String],
+  recent: impl Iterator<Item = &'a String>,
+) -> Vec<String> {
+  let mut channels = configured.to_vec();
+  for channel_id in recent {
+    if !channels.contains(channel_id) {
+      channels.push(channel_id.clone());
+    }
+  }
+  channels
+}
+
+// what a prefetch round does with an episode
+#[derive(Debug, PartialEq, Eq)]
+enum Plan {
+  Download,
+  // downloaded by an earlier round or by a listener, or denied by the
+  // channel filter. evicted ones are downloaded again.
+  Skip,
+  // downloads share the yt-dlp budget with the listeners, back off
+  // until the next round while it's all taken
+  Postpone,
+}
+
+fn plan(stored: bool, allowed: bool, free_ytdlp_slots: usize) -> Plan {
+  if stored || !allowed {
+    Plan::Skip
+  } else if free_ytdlp_slots == 0 {
+    Plan::Postpone
+  } else {
+    Plan::Download
+  }
+}
+
+pub async fn run(audio_store: Arc<AudioStoreRef>) {
+  let Some(interval) = *PREFETCH_INTERVAL else {
+    return;
+  };
+
+  info!("prefetching new episodes every {:?}", interval);
+  let downloader = YtdlpFile::new(audio_store.clone());
+  let mut ticker = tokio::time::interval(interval);
+
+  loop {
+    ticker.tick().await;
+    for channel_id in channels() {
+      prefetch_channel(&downloader, &audio_store, &channel_id).await;
+    }
+  }
+}
+
+async fn prefetch_channel(
+  downloader: &YtdlpFile,
+  audio_store: &AudioStoreRef,
+  channel_id: &str,
+) {
+  // the filter may have changed since the feed was requested
+  if let Err(e) = channel_filter::check_channel(channel_id, None).await {
+    info!("prefetch: skipping channel {}: {}", channel_id, e);
+    return;
+  }
+
+  let podcast = match feed::refresh_channel(channel_id).await {
+    Ok(podcast) => podcast,
+    Err(e) => {
+      warn!("prefetch: failed to harvest channel {}: {}", channel_id, e);
+      return;
+    }
+  };
+
+  for episode in podcast.episodes.iter().take(*PREFETCH_EPISODES) {
+    let video_id = &episode.video_id;
+    let stored = match audio_store.contains(video_id, AudioFormat::M4a).await {
+      Ok(stored) => stored,
+      Err(e) => {
+        warn!("prefetch: {}", e);
+        return;
+      }
+    };
+    let allowed = stored
+      || match channel_filter::check_video(video_id).await {
+        Ok(()) => true,
+        Err(e) => {
+          info!("prefetch: skipping {}: {}", video_id, e);
+          false
+        }
+      };
+
+    match plan(stored, allowed, YTDLP_MUTEX.available_permits()) {
+      Plan::Download => (),
+      Plan::Skip => continue,
+      Plan::Postpone => {
+        info!("prefetch: yt-dlp is busy, postponing {}", channel_id);
+        return;
+      }
+    }
+
+    // spawned so that a shutdown lets it finish instead of cutting it
+    // off halfway, the drain waits for it
+    let download = {
+      let (downloader, video_id) = (downloader.clone(), video_id.clone());
+      tokio::spawn(async move { downloader.download(&video_id).await })
+    };
+    match download.await {
+      Ok(Ok(_)) => info!("prefetch: downloaded {}", video_id),
+      Ok(Err(e)) => warn!("prefetch: failed to download {}: {}", video_id, e),
+      Err(e) => warn!("prefetch: download of {} panicked: {}", video_id, e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_merge_channels() {
+    let configured = vec!["a".to_string(), "b".to_string()];
+    let recent = ["b".to_string(), "c".to_string()];
+    assert_eq!(merge_channels(&configured, recent.iter()), ["a", "b", "c"]);
+  }
+
+  #[test]
+  fn test_plan() {
+    assert_eq!(plan(false, true, 1), Plan::Download);
+    assert_eq!(plan(true, true, 1), Plan::Skip);
+    assert_eq!(plan(false, false, 1), Plan::Skip);
+    // stored or denied episodes don't need a slot
+    assert_eq!(plan(true, true, 0), Plan::Skip);
+    assert_eq!(plan(false, false, 0), Plan::Skip);
+    assert_eq!(plan(false, true, 0), Plan::Postpone);
+  }
+}
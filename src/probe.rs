@@ -0,0 +1,99 @@
+use std::sync::{Arc, LazyLock};
+
+use tracing::info;
+
+use crate::{
+  audio_store::AudioStoreRef,
+  config::{ExtractorConfig, CONFIG},
+  extractor, harvestor,
+  health::{BackendId, HEALTH},
+  invidious::InvidiousInstance,
+  piped::PipedInstance,
+  util::with_timeout,
+};
+
+// a channel known to work, to probe the harvestors with an open circuit
+// in the background. off when unset.
+static HEALTH_PROBE_CHANNEL: LazyLock<Option<String>> =
+  LazyLock::new(|| env_id("HEALTH_PROBE_CHANNEL"));
+
+// the same for the extractors, with a video
+static HEALTH_PROBE_VIDEO: LazyLock<Option<String>> =
+  LazyLock::new(|| env_id("HEALTH_PROBE_VIDEO"));
+
+fn env_id(name: &str) -> Option<String> {
+  std::env::var(name).ok().filter(|s| !s.is_empty())
+}
+
+// probe the backends whose circuit is open every probe interval, so
+// they come back without a listener paying for the failed probes
+pub async fn run(audio_store: Arc<AudioStoreRef>) {
+  let channel_id = HEALTH_PROBE_CHANNEL.as_deref();
+  let video_id = HEALTH_PROBE_VIDEO.as_deref();
+  if channel_id.is_none() && video_id.is_none() {
+    return;
+  }
+
+  info!("probing open circuits every {:?}", HEALTH.probe_interval());
+  let mut ticker = tokio::time::interval(HEALTH.probe_interval());
+  loop {
+    ticker.tick().await;
+    if let Some(channel_id) = channel_id {
+      probe_harvestors(channel_id).await;
+    }
+    if let Some(video_id) = video_id {
+      probe_extractors(video_id, &audio_store).await;
+    }
+  }
+}
+
+async fn probe_harvestors(channel_id: &str) {
+  let piped = PipedInstance::default();
+  let invidious = InvidiousInstance::default();
+
+  for config in &CONFIG.harvestors {
+    let name = config.backend.name();
+    let id = BackendId::harvestor(name);
+    if !HEALTH.claim_probe(id) {
+      continue;
+    }
+    let Some(harvestor) =
+      harvestor::build(&config.backend, Some(&piped), &invidious)
+    else {
+      continue;
+    };
+
+    let harvest = harvestor.harvest(channel_id);
+    let harvest = with_timeout(config.timeout(), name, harvest);
+    if let Err(e) = HEALTH.track(id, harvest).await {
+      info!("probe: {id} is still failing: {e}");
+    }
+  }
+}
+
+async fn probe_extractors(video_id: &str, audio_store: &Arc<AudioStoreRef>) {
+  let piped = PipedInstance::default();
+  let invidious = InvidiousInstance::default();
+
+  for config in &CONFIG.extractors {
+    // answers from the audio store once the video is downloaded, which
+    // says nothing about youtube
+    if matches!(config.backend, ExtractorConfig::YtdlpFile) {
+      continue;
+    }
+    let name = config.backend.name();
+    let id = BackendId::extractor(name);
+    if !HEALTH.claim_probe(id) {
+      continue;
+    }
+
+    let extractor =
+      extractor::build(&config.backend, audio_store, &piped, &invidious);
+    let extraction = extractor.extract(video_id);
+    let extraction = with_timeout(config.timeout(), name, extraction);
+    // the extraction itself is dropped, nothing is downloaded
+    if let Err(e) = HEALTH.track(id, extraction).await {
+      info!("probe: {id} is still failing: {e}");
+    }
+  }
+}
@@ -0,0 +1,230 @@
+use std::{
+  net::SocketAddr,
+  sync::{LazyLock, Mutex},
+  time::{Duration, Instant},
+};
+
+use axum::extract::ConnectInfo;
+use lru_time_cache::LruCache;
+use tracing::warn;
+
+use crate::{Error, Result};
+
+// feed requests that need a harvest, e.g. "30/m". cached feeds are free.
+pub static FEED_RATE_LIMIT: LazyLock<RateLimiter> =
+  LazyLock::new(|| RateLimiter::from_env("FEED_RATE_LIMIT"));
+
+// audio requests for videos not in the audio store, e.g. "20/h"
+pub static DOWNLOAD_RATE_LIMIT: LazyLock<RateLimiter> =
+  LazyLock::new(|| RateLimiter::from_env("DOWNLOAD_RATE_LIMIT"));
+
+// the header carrying the client ip when behind a proxy, e.g.
+// fly-client-ip. the peer address is used otherwise.
+static CLIENT_IP_HEADER: LazyLock<Option<String>> =
+  LazyLock::new(|| std::env::var("CLIENT_IP_HEADER").ok());
+
+// forget about the least recently seen clients beyond this many
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rate {
+  count: u32,
+  per: Duration,
+}
+
+// "<count>/<s|m|h|d>"
+fn parse_rate(s: &str) -> Option<Rate> {
+  let (count, unit) = s.trim().split_once('/')?;
+  let per = match unit.trim() {
+    "s" => 1,
+    "m" => 60,
+    "h" => 60 * 60,
+    "d" => 24 * 60 * 60,
+    _ => return None,
+  };
+  let count = count.trim().parse().ok().filter(|n| *n > 0)?;
+  Some(Rate {
+    count,
+    per: Duration::from_secs(per),
+  })
+}
+
+struct Bucket {
+  tokens: f64,
+  updated_at: Instant,
+}
+
+// a token bucket per client, holding up to `count` requests and
+// refilled at `count` per `per`
+pub struct RateLimiter {
+  // none when unlimited
+  rate: Option<Rate>,
+  buckets: Mutex<LruCache<String, Bucket>>,
+  // the items clients were charged for in the last period
+  charged: Mutex<LruCache<String, ()>>,
+}
+
+impl RateLimiter {
+  fn from_env(name: &str) -> Self {
+    let rate = std::env::var(name).ok().and_then(|s| {
+      let rate = parse_rate(&s);
+      if rate.is_none() {
+        warn!("ignoring invalid {name}: {s}");
+      }
+      rate
+    });
+    Self::new(rate)
+  }
+
+  fn new(rate: Option<Rate>) -> Self {
+    let per = rate.map(|r| r.per).unwrap_or_default();
+    Self {
+      rate,
+      buckets: Mutex::new(LruCache::with_capacity(MAX_CLIENTS)),
+      charged: Mutex::new(LruCache::with_expiry_duration_and_capacity(
+        per,
+        MAX_CLIENTS,
+      )),
+    }
+  }
+
+  // take `cost` requests from the client's budget, or tell when to
+  // come back
+  pub fn check(&self, client: &str, cost: u32) -> Result<()> {
+    self.check_at(client, cost, Instant::now())
+  }
+
+  // like `check` with a cost of one, but the client is only charged once
+  // per item and period, e.g. for all the range requests of an episode
+  pub fn check_once(&self, client: &str, item: &str) -> Result<()> {
+    if self.rate.is_none() {
+      return Ok(());
+    }
+    let key = format!("{client} {item}");
+    if self.charged.lock().unwrap().get(&key).is_some() {
+      return Ok(());
+    }
+    self.check(client, 1)?;
+    self.charged.lock().unwrap().insert(key, ());
+    Ok(())
+  }
+
+  fn check_at(&self, client: &str, cost: u32, now: Instant) -> Result<()> {
+    let Some(rate) = self.rate else {
+      return Ok(());
+    };
+    if cost == 0 {
+      return Ok(());
+    }
+
+    let capacity = rate.count as f64;
+    let per_sec = capacity / rate.per.as_secs_f64();
+    let cost = (cost as f64).min(capacity);
+
+    let mut buckets = self.buckets.lock().unwrap();
+    let bucket = buckets.entry(client.to_string()).or_insert_with(|| Bucket {
+      tokens: capacity,
+      updated_at: now,
+    });
+    let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+    bucket.tokens = (bucket.tokens + elapsed * per_sec).min(capacity);
+    bucket.updated_at = now;
+
+    if bucket.tokens < cost {
+      let wait = (cost - bucket.tokens) / per_sec;
+      return Err(Error::TooManyRequests(Duration::from_secs_f64(wait.ceil())));
+    }
+
+    bucket.tokens -= cost;
+    Ok(())
+  }
+}
+
+// the address of the client, to rate limit anonymous requests by
+pub fn client_ip(parts: &http::request::Parts) -> String {
+  let from_header = CLIENT_IP_HEADER.as_ref().and_then(|name| {
+    let value = parts.headers.get(name.as_str())?.to_str().ok()?;
+    // the client can put anything in x-forwarded-for, only the address
+    // appended by our proxy can be trusted
+    let ip = value.rsplit(',').next()?.trim();
+    Some(ip.to_string())
+  });
+
+  from_header
+    .or_else(|| {
+      let ConnectInfo(addr) =
+        parts.extensions.get::<ConnectInfo<SocketAddr>>()?;
+      Some(addr.ip().to_string())
+    })
+    .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_rate() {
+    let rate = |count, secs| {
+      Some(Rate {
+        count,
+        per: Duration::from_secs(secs),
+      })
+    };
+    assert_eq!(parse_rate("30/m"), rate(30, 60));
+    assert_eq!(parse_rate(" 20 / h "), rate(20, 3600));
+    assert_eq!(parse_rate("0/m"), None);
+    assert_eq!(parse_rate("30"), None);
+    assert_eq!(parse_rate("30/week"), None);
+  }
+
+  #[test]
+  fn test_rate_limiter() {
+    let limiter = RateLimiter::new(parse_rate("2/m"));
+    let now = Instant::now();
+
+    assert!(limiter.check_at("a", 1, now).is_ok());
+    assert!(limiter.check_at("a", 1, now).is_ok());
+    // one comes back every 30 seconds
+    match limiter.check_at("a", 1, now) {
+      Err(Error::TooManyRequests(wait)) => {
+        assert_eq!(wait, Duration::from_secs(30))
+      }
+      _ => panic!("should be limited"),
+    }
+    // other clients have their own budget
+    assert!(limiter.check_at("b", 2, now).is_ok());
+
+    let later = now + Duration::from_secs(30);
+    assert!(limiter.check_at("a", 1, later).is_ok());
+    assert!(limiter.check_at("a", 1, later).is_err());
+
+    let unlimited = RateLimiter::new(None);
+    assert!((0..100).all(|_| unlimited.check_at("a", 1, now).is_ok()));
+  }
+
+  #[test]
+  fn test_check_once() {
+    let limiter = RateLimiter::new(parse_rate("1/h"));
+
+    assert!(limiter.check_once("a", "video1").is_ok());
+    // the range requests of the same episode are free
+    assert!(limiter.check_once("a", "video1").is_ok());
+    assert!(limiter.check_once("a", "video2").is_err());
+    assert!(limiter.check_once("b", "video2").is_ok());
+  }
+
+  #[test]
+  fn test_rate_limiter_evicts() {
+    let limiter = RateLimiter::new(parse_rate("1/h"));
+    let now = Instant::now();
+
+    assert!(limiter.check_at("a", 1, now).is_ok());
+    for i in 0..MAX_CLIENTS {
+      let _ = limiter.check_at(&i.to_string(), 1, now);
+    }
+    assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_CLIENTS);
+    // the least recently seen client was forgotten
+    assert!(limiter.check_at("a", 1, now).is_ok());
+  }
+}
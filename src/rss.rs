@@ -2,6 +2,7 @@
 use std::io::Cursor;
 
 use atom_syndication::{Entry, Feed};
+use chrono::{DateTime, Utc};
 use itertools::Itertools;
 
 use crate::{podcast::Thumbnail, Result, W};
@@ -23,6 +24,7 @@ pub struct RssEpisode {
   pub description: String,
   pub thumbnail: Thumbnail,
   pub author: String,
+  pub published: Option<DateTime<Utc>>,
 }
 
 impl RssEpisode {
@@ -36,6 +38,7 @@ impl RssEpisode {
       .map(|x| x.name.clone())
       .unwrap_or_default();
     let title = entry.title.to_string();
+    let published = entry.published.map(|x| x.with_timezone(&Utc));
 
     Ok(RssEpisode {
       title,
@@ -43,6 +46,7 @@ impl RssEpisode {
       thumbnail,
       video_id,
       author,
+      published,
     })
   }
 }
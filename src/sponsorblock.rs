@@ -0,0 +1,272 @@
+use std::{
+  collections::HashMap, fmt, path::PathBuf, str::FromStr, sync::LazyLock,
+};
+
+use async_trait::async_trait;
+use itertools::Itertools;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::{Error, Result};
+
+const DEFAULT_SPONSORBLOCK_API: &str = "https://sponsor.ajay.app";
+
+const KNOWN_CATEGORIES: &[&str] = &[
+  "sponsor",
+  "selfpromo",
+  "interaction",
+  "intro",
+  "outro",
+  "preview",
+  "music_offtopic",
+  "filler",
+];
+
+// where to look up the segments: either the url of a sponsorblock
+// compatible api (e.g. a local mirror), or the path of a json file
+// mapping video ids to segments in the same format as the api returns.
+pub static SEGMENT_SOURCE: LazyLock<Box<dyn SegmentSource>> =
+  LazyLock::new(|| {
+    let source = std::env::var("SPONSORBLOCK_SOURCE")
+      .unwrap_or_else(|_| DEFAULT_SPONSORBLOCK_API.to_owned());
+
+    if source.starts_with("http://") || source.starts_with("https://") {
+      Box::new(SponsorBlockApi::new(source))
+    } else {
+      Box::new(SegmentFile::new(source))
+    }
+  });
+
+// a sorted set of sponsorblock categories, e.g. "selfpromo,sponsor"
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Categories(Vec<String>);
+
+impl Categories {
+  // used to tell apart the stored files cut with different categories
+  pub fn tag(&self) -> String {
+    self.0.join("+")
+  }
+
+  fn contains(&self, category: &str) -> bool {
+    self.0.iter().any(|c| c == category)
+  }
+}
+
+impl FromStr for Categories {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let categories: Vec<String> = s
+      .split(',')
+      .map(str::trim)
+      .filter(|c| !c.is_empty())
+      .map(|c| {
+        KNOWN_CATEGORIES
+          .contains(&c)
+          .then(|| c.to_string())
+          .ok_or_else(|| Error::InvalidSponsorBlockCategory(c.to_string()))
+      })
+      .collect::<Result<_>>()?;
+
+    if categories.is_empty() {
+      return Err(Error::InvalidSponsorBlockCategory(s.to_string()));
+    }
+
+    Ok(Self(categories.into_iter().sorted().dedup().collect()))
+  }
+}
+
+impl TryFrom<String> for Categories {
+  type Error = Error;
+
+  fn try_from(s: String) -> Result<Self> {
+    s.parse()
+  }
+}
+
+impl fmt::Display for Categories {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0.join(","))
+  }
+}
+
+// a time range to cut out, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+  pub start: f64,
+  pub end: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentEntry {
+  segment: (f64, f64),
+  category: String,
+  action_type: Option<String>,
+}
+
+#[async_trait]
+pub trait SegmentSource: Send + Sync {
+  async fn segments(
+    &self,
+    video_id: &str,
+    categories: &Categories,
+  ) -> Result<Vec<Segment>>;
+}
+
+pub struct SponsorBlockApi {
+  api_url: String,
+}
+
+impl SponsorBlockApi {
+  pub fn new(api_url: String) -> Self {
+    Self { api_url }
+  }
+}
+
+#[async_trait]
+impl SegmentSource for SponsorBlockApi {
+  async fn segments(
+    &self,
+    video_id: &str,
+    categories: &Categories,
+  ) -> Result<Vec<Segment>> {
+    let url = format!("{}/api/skipSegments", self.api_url);
+    let categories_json = serde_json::to_string(&categories.0)?;
+    let resp = reqwest::Client::new()
+      .get(url)
+      .query(&[("videoID", video_id), ("categories", &categories_json)])
+      .send()
+      .await?;
+
+    // no segments were submitted for the video
+    if resp.status() == StatusCode::NOT_FOUND {
+      return Ok(vec![]);
+    }
+
+    let entries: Vec<SegmentEntry> = resp.error_for_status()?.json().await?;
+    Ok(select_segments(entries, categories))
+  }
+}
+
+pub struct SegmentFile {
+  path: PathBuf,
+}
+
+impl SegmentFile {
+  pub fn new(path: impl Into<PathBuf>) -> Self {
+    Self { path: path.into() }
+  }
+}
+
+#[async_trait]
+impl SegmentSource for SegmentFile {
+  async fn segments(
+    &self,
+    video_id: &str,
+    categories: &Categories,
+  ) -> Result<Vec<Segment>> {
+    let content = tokio::fs::read(&self.path).await?;
+    let mut videos: HashMap<String, Vec<SegmentEntry>> =
+      serde_json::from_slice(&content)?;
+    let entries = videos.remove(video_id).unwrap_or_default();
+    Ok(select_segments(entries, categories))
+  }
+}
+
+// keep the segments to skip in the given categories, sorted and with
+// overlapping ones merged.
+fn select_segments(
+  entries: Vec<SegmentEntry>,
+  categories: &Categories,
+) -> Vec<Segment> {
+  let segments = entries
+    .into_iter()
+    .filter(|e| e.action_type.as_deref().unwrap_or("skip") == "skip")
+    .filter(|e| categories.contains(&e.category))
+    .map(|e| Segment {
+      start: e.segment.0,
+      end: e.segment.1,
+    })
+    .filter(|s| s.end > s.start)
+    .sorted_by(|a, b| a.start.total_cmp(&b.start));
+
+  let mut merged: Vec<Segment> = vec![];
+  for segment in segments {
+    match merged.last_mut() {
+      Some(last) if segment.start <= last.end => {
+        last.end = last.end.max(segment.end);
+      }
+      _ => merged.push(segment),
+    }
+  }
+
+  merged
+}
+
+// the ffmpeg audio filter that cuts out the segments
+pub fn ffmpeg_filter(segments: &[Segment]) -> String {
+  let ranges = segments
+    .iter()
+    .map(|s| format!("between(t,{},{})", s.start, s.end))
+    .join("+");
+
+  format!("aselect='not({ranges})',asetpts=N/SR/TB")
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_parse_categories() {
+    let categories: Categories = "sponsor, selfpromo,sponsor".parse().unwrap();
+    assert_eq!(categories.to_string(), "selfpromo,sponsor");
+    assert_eq!(categories.tag(), "selfpromo+sponsor");
+
+    assert!("sponsor,ads".parse::<Categories>().is_err());
+    assert!(",".parse::<Categories>().is_err());
+  }
+
+  #[tokio::test]
+  async fn test_segment_file() {
+    let path = std::env::temp_dir()
+      .join(format!("sponsorblock-{}.json", std::process::id()));
+    let fixture = serde_json::json!({
+      "abc": [
+        {"segment": [30.0, 40.0], "category": "sponsor", "actionType": "skip"},
+        {"segment": [5.0, 10.0], "category": "selfpromo", "actionType": "skip"},
+        {"segment": [35.0, 50.0], "category": "sponsor", "actionType": "skip"},
+        {"segment": [60.0, 70.0], "category": "intro", "actionType": "skip"},
+        {"segment": [80.0, 90.0], "category": "sponsor", "actionType": "mute"},
+      ]
+    });
+    std::fs::write(&path, fixture.to_string()).unwrap();
+
+    let source = SegmentFile::new(&path);
+    let categories = "sponsor,selfpromo".parse().unwrap();
+    let segments = source.segments("abc", &categories).await.unwrap();
+    let unknown = source.segments("xyz", &categories).await.unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(
+      segments,
+      [
+        Segment {
+          start: 5.0,
+          end: 10.0
+        },
+        Segment {
+          start: 30.0,
+          end: 50.0
+        },
+      ]
+    );
+    assert_eq!(unknown, []);
+    assert_eq!(
+      ffmpeg_filter(&segments),
+      "aselect='not(between(t,5,10)+between(t,30,50))',asetpts=N/SR/TB"
+    );
+  }
+}
@@ -0,0 +1,361 @@
+use std::{
+  path::PathBuf,
+  sync::{Arc, LazyLock, Mutex},
+  time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use axum::extract::{FromRequestParts, Path, Query};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use rand::RngCore as _;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+  rate_limit::client_ip,
+  util::{constant_time_eq, hmac_sign, hmac_verify},
+  Error, Result,
+};
+
+// feeds and audio require a subscriber token when set. the file is
+// reloaded when it changes, see `cli` for managing it.
+static SUBSCRIBERS_FILE: LazyLock<Option<PathBuf>> =
+  LazyLock::new(|| std::env::var("SUBSCRIBERS_FILE").ok().map(PathBuf::from));
+
+// seconds the audio urls in a feed stay valid. generous, as podcast
+// apps keep the urls of old episodes and only some refresh them with the
+// feed. the expiry is rounded up to a day so the feed stays the same for
+// a while.
+static AUDIO_GRANT_TTL: LazyLock<Duration> = LazyLock::new(|| {
+  let secs = std::env::var("AUDIO_GRANT_TTL")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(90 * DAY);
+  Duration::from_secs(secs)
+});
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscribersFile {
+  #[serde(default)]
+  subscribers: Vec<Subscriber>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Subscriber {
+  pub name: String,
+  token: String,
+}
+
+// the subscribers as of the last modification time of the file
+type Loaded = Option<(SystemTime, Arc<Vec<Subscriber>>)>;
+
+static LOADED: LazyLock<Mutex<Loaded>> = LazyLock::new(Default::default);
+
+// none when subscriber tokens are off
+fn subscribers() -> Option<Arc<Vec<Subscriber>>> {
+  let path = SUBSCRIBERS_FILE.as_ref()?;
+  let modified = std::fs::metadata(path)
+    .and_then(|m| m.modified())
+    .unwrap_or(SystemTime::UNIX_EPOCH);
+
+  let mut loaded = LOADED.lock().unwrap();
+  match &*loaded {
+    Some((at, subscribers)) if *at == modified => Some(subscribers.clone()),
+    _ => {
+      // an unreadable file locks everyone out rather than letting
+      // everyone in
+      let subscribers = load(path).unwrap_or_else(|e| {
+        warn!("failed loading {}: {}", path.display(), e);
+        SubscribersFile::default()
+      });
+      let subscribers = Arc::new(subscribers.subscribers);
+      *loaded = Some((modified, subscribers.clone()));
+      Some(subscribers)
+    }
+  }
+}
+
+fn load(path: &std::path::Path) -> Result<SubscribersFile> {
+  if !path.exists() {
+    return Ok(SubscribersFile::default());
+  }
+  let content = std::fs::read_to_string(path)?;
+  toml::from_str(&content).map_err(|e| Error::Config(e.to_string()))
+}
+
+fn save(path: &std::path::Path, file: &SubscribersFile) -> Result<()> {
+  let content =
+    toml::to_string(file).map_err(|e| Error::Config(e.to_string()))?;
+  std::fs::write(path, content)?;
+  Ok(())
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs()
+}
+
+// who is requesting a feed, extracted from ?token=
+pub struct Access {
+  subscriber: Option<Subscriber>,
+  client: String,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+  token: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Access
+where
+  S: Send + Sync,
+{
+  type Rejection = Error;
+
+  async fn from_request_parts(
+    parts: &mut http::request::Parts,
+    state: &S,
+  ) -> Result<Self> {
+    let Some(subscribers) = subscribers() else {
+      let client = client_ip(parts);
+      return Ok(Access {
+        subscriber: None,
+        client,
+      });
+    };
+
+    let query = Query::<TokenQuery>::from_request_parts(parts, state)
+      .await
+      .map_err(|_| Error::InvalidToken)?;
+    let token = query.0.token.ok_or(Error::InvalidToken)?;
+    let subscriber = find_by_token(&subscribers, &token)?;
+
+    Ok(Access {
+      subscriber: Some(subscriber.clone()),
+      client: subscriber.name.clone(),
+    })
+  }
+}
+
+fn find_by_token<'a>(
+  subscribers: &'a [Subscriber],
+  token: &str,
+) -> Result<&'a Subscriber> {
+  subscribers
+    .iter()
+    .find(|s| constant_time_eq(s.token.as_bytes(), token.as_bytes()))
+    .ok_or(Error::InvalidToken)
+}
+
+impl Access {
+  // the subscriber, or the ip without subscriber tokens
+  pub fn client(&self) -> &str {
+    &self.client
+  }
+
+  // the query parameters to put on a url derived from the feed url
+  pub fn token_query(&self) -> Option<(&'static str, String)> {
+    let subscriber = self.subscriber.as_ref()?;
+    Some(("token", subscriber.token.clone()))
+  }
+
+  // a signed and expiring grant to the audio of one video, so that the
+  // token itself doesn't end up in the enclosure urls. removing the
+  // subscriber revokes it before it expires.
+  pub fn audio_grant(&self, video_id: &str) -> Option<(&'static str, String)> {
+    let subscriber = self.subscriber.as_ref()?;
+    let expires = (unix_now() + AUDIO_GRANT_TTL.as_secs()).div_ceil(DAY) * DAY;
+    let message = format!("{video_id}:{expires}");
+    let signature = hmac_sign(subscriber.token.as_bytes(), &message);
+    let name = URL_SAFE_NO_PAD.encode(&subscriber.name);
+    Some(("grant", format!("{name}.{expires}.{signature}")))
+  }
+}
+
+// checks the grant on an audio, chapters or transcript url
+pub struct AudioAccess {
+  client: String,
+}
+
+impl AudioAccess {
+  // the subscriber, or the ip without subscriber tokens
+  pub fn client(&self) -> &str {
+    &self.client
+  }
+}
+
+#[derive(Deserialize)]
+struct GrantQuery {
+  grant: Option<String>,
+  token: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AudioAccess
+where
+  S: Send + Sync,
+{
+  type Rejection = Error;
+
+  async fn from_request_parts(
+    parts: &mut http::request::Parts,
+    state: &S,
+  ) -> Result<Self> {
+    let Some(subscribers) = subscribers() else {
+      let client = client_ip(parts);
+      return Ok(AudioAccess { client });
+    };
+
+    let Path(video_id) = Path::<String>::from_request_parts(parts, state)
+      .await
+      .map_err(|_| Error::InvalidToken)?;
+    let query = Query::<GrantQuery>::from_request_parts(parts, state)
+      .await
+      .map_err(|_| Error::InvalidToken)?;
+
+    let subscriber = match query.0 {
+      GrantQuery {
+        grant: Some(grant), ..
+      } => verify_grant(&subscribers, &video_id, &grant, unix_now())?,
+      // the subscriber token works everywhere
+      GrantQuery {
+        token: Some(token), ..
+      } => find_by_token(&subscribers, &token)?,
+      _ => return Err(Error::InvalidToken),
+    };
+
+    Ok(AudioAccess {
+      client: subscriber.name.clone(),
+    })
+  }
+}
+
+fn verify_grant<'a>(
+  subscribers: &'a [Subscriber],
+  video_id: &str,
+  grant: &str,
+  now: u64,
+) -> Result<&'a Subscriber> {
+  let mut parts = grant.splitn(3, '.');
+  let (Some(name), Some(expires), Some(signature)) =
+    (parts.next(), parts.next(), parts.next())
+  else {
+    return Err(Error::InvalidToken);
+  };
+
+  let name = URL_SAFE_NO_PAD
+    .decode(name)
+    .ok()
+    .and_then(|name| String::from_utf8(name).ok())
+    .ok_or(Error::InvalidToken)?;
+  let expires: u64 = expires.parse().map_err(|_| Error::InvalidToken)?;
+  if expires < now {
+    return Err(Error::InvalidToken);
+  }
+
+  // a removed subscriber's grants stop working right away
+  let subscriber = subscribers
+    .iter()
+    .find(|s| s.name == name)
+    .ok_or(Error::InvalidToken)?;
+  let message = format!("{video_id}:{expires}");
+  if !hmac_verify(subscriber.token.as_bytes(), &message, signature) {
+    return Err(Error::InvalidToken);
+  }
+
+  Ok(subscriber)
+}
+
+fn generate_token() -> String {
+  let mut bytes = [0u8; 24];
+  rand::thread_rng().fill_bytes(&mut bytes);
+  URL_SAFE_NO_PAD.encode(bytes)
+}
+
+// `subscriber add <name>`, `subscriber remove <name>` or `subscriber list`
+// on the SUBSCRIBERS_FILE
+pub fn cli(args: &[String]) -> Result<()> {
+  let path = SUBSCRIBERS_FILE
+    .as_ref()
+    .ok_or_else(|| Error::Config("SUBSCRIBERS_FILE is not set".into()))?;
+  let mut file = load(path)?;
+
+  match args {
+    [cmd, name] if cmd == "add" => {
+      if file.subscribers.iter().any(|s| &s.name == name) {
+        return Err(Error::Config(format!("subscriber {name} exists")));
+      }
+      let token = generate_token();
+      println!("{token}");
+      file.subscribers.push(Subscriber {
+        name: name.clone(),
+        token,
+      });
+    }
+    [cmd, name] if cmd == "remove" => {
+      let count = file.subscribers.len();
+      file.subscribers.retain(|s| &s.name != name);
+      if file.subscribers.len() == count {
+        return Err(Error::Config(format!("no subscriber named {name}")));
+      }
+    }
+    [cmd] if cmd == "list" => {
+      for subscriber in &file.subscribers {
+        println!("{}\t{}", subscriber.name, subscriber.token);
+      }
+      return Ok(());
+    }
+    _ => {
+      let usage = "usage: subscriber add <name> | remove <name> | list";
+      return Err(Error::Config(usage.into()));
+    }
+  }
+
+  save(path, &file)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_audio_grant() {
+    let alice = Subscriber {
+      name: "alice.a".into(),
+      token: "secret".into(),
+    };
+    let subscribers = vec![alice.clone()];
+
+    let access = Access {
+      subscriber: Some(alice),
+      client: String::new(),
+    };
+    let (_, grant) = access.audio_grant("video1").unwrap();
+    let now = unix_now();
+
+    assert!(verify_grant(&subscribers, "video1", &grant, now).is_ok());
+    // only for that video
+    assert!(verify_grant(&subscribers, "video2", &grant, now).is_err());
+    // not after it expired
+    let later = now + AUDIO_GRANT_TTL.as_secs() + 2 * DAY;
+    assert!(verify_grant(&subscribers, "video1", &grant, later).is_err());
+    // not once the subscriber is removed
+    assert!(verify_grant(&[], "video1", &grant, now).is_err());
+    // nor once their token is replaced
+    let rotated = Subscriber {
+      name: "alice.a".into(),
+      token: "other".into(),
+    };
+    assert!(verify_grant(&[rotated], "video1", &grant, now).is_err());
+
+    // nor with a later expiry
+    let tampered = grant.replacen('.', ".9", 1);
+    assert!(verify_grant(&subscribers, "video1", &tampered, now).is_err());
+  }
+}
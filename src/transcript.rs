@@ -0,0 +1,169 @@
+use std::sync::LazyLock;
+
+use axum::{
+  extract::{Path, Query},
+  response::IntoResponse,
+};
+use regex::Regex;
+use reqwest::header;
+use serde::Deserialize;
+
+use crate::{
+  audio_signature::{self, SignedAudio},
+  channel_filter,
+  rate_limit::DOWNLOAD_RATE_LIMIT,
+  subscriber::AudioAccess,
+  video_info, Error, Result, INSTANCE_PUBLIC_URL,
+};
+
+// the preferred caption language, can be overridden with ?lang=
+pub static TRANSCRIPT_LANG: LazyLock<String> = LazyLock::new(|| {
+  std::env::var("TRANSCRIPT_LANG").unwrap_or_else(|_| "en".to_owned())
+});
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+  #[default]
+  Vtt,
+  Srt,
+}
+
+impl TranscriptFormat {
+  pub fn mime_type(self) -> &'static str {
+    match self {
+      TranscriptFormat::Vtt => "text/vtt",
+      TranscriptFormat::Srt => "application/x-subrip",
+    }
+  }
+
+  // the value of ?format=
+  pub fn name(self) -> &'static str {
+    match self {
+      TranscriptFormat::Vtt => "vtt",
+      TranscriptFormat::Srt => "srt",
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TranscriptQuery {
+  #[serde(default)]
+  format: TranscriptFormat,
+  lang: Option<String>,
+}
+
+pub fn transcript_url(video_id: &str) -> String {
+  let url = format!("{}/transcript/{}", &*INSTANCE_PUBLIC_URL, video_id);
+  match audio_signature::sign(video_id) {
+    Some((name, signature)) => format!("{url}?{name}={signature}"),
+    None => url,
+  }
+}
+
+pub async fn get_transcript(
+  Path(video_id): Path<String>,
+  Query(query): Query<TranscriptQuery>,
+  // guarded like the audio, as looking up the video runs yt-dlp
+  (_, access): (SignedAudio, AudioAccess),
+) -> Result<impl IntoResponse> {
+  let lang = query.lang.unwrap_or_else(|| TRANSCRIPT_LANG.clone());
+  eprintln!(
+    "client requesting transcript: {} (lang: {})",
+    video_id, lang
+  );
+
+  if !video_info::is_cached(&video_id) {
+    DOWNLOAD_RATE_LIMIT.check(access.client(), 1)?;
+  }
+  channel_filter::check_video(&video_id).await?;
+
+  let info = video_info::fetch(&video_id).await?;
+  let url = info
+    .caption_url(&lang)
+    .ok_or_else(|| Error::TranscriptNotFound(format!("{video_id} ({lang})")))?;
+  let vtt = reqwest::get(url).await?.error_for_status()?.text().await?;
+
+  let body = match query.format {
+    TranscriptFormat::Vtt => vtt,
+    TranscriptFormat::Srt => vtt_to_srt(&vtt),
+  };
+
+  Ok(([(header::CONTENT_TYPE, query.format.mime_type())], body))
+}
+
+fn vtt_to_srt(vtt: &str) -> String {
+  static TIMING_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+      r"^((?:\d+:)?\d{2}:\d{2})\.(\d{3}) --> ((?:\d+:)?\d{2}:\d{2})\.(\d{3})",
+    )
+    .unwrap()
+  });
+  // inline timestamps and styling of youtube's auto-generated captions
+  static TAG_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+  let with_hours = |t: &str| {
+    if t.matches(':').count() == 1 {
+      format!("00:{t}")
+    } else {
+      t.to_string()
+    }
+  };
+
+  let mut srt = String::new();
+  let mut index = 0;
+  // cue blocks are separated by blank lines, the ones without a timing
+  // line are the header, comments and styles.
+  for block in vtt.replace("\r\n", "\n").split("\n\n") {
+    let mut lines = block.lines().skip_while(|l| !TIMING_REGEX.is_match(l));
+    let Some(timing) = lines.next() else {
+      continue;
+    };
+    let captures = TIMING_REGEX.captures(timing).unwrap();
+
+    index += 1;
+    srt.push_str(&format!(
+      "{}\n{},{} --> {},{}\n",
+      index,
+      with_hours(&captures[1]),
+      &captures[2],
+      with_hours(&captures[3]),
+      &captures[4]
+    ));
+    for line in lines {
+      srt.push_str(&TAG_REGEX.replace_all(line, ""));
+      srt.push('\n');
+    }
+    srt.push('\n');
+  }
+
+  srt
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  fn test_vtt_to_srt() {
+    let vtt = "WEBVTT\n\
+      Kind: captions\n\
+      Language: en\n\
+      \n\
+      NOTE a comment\n\
+      \n\
+      00:01.000 --> 00:04.000 align:start position:0%\n\
+      hello <c>world</c>\n\
+      \n\
+      intro\n\
+      01:00:02.500 --> 01:00:03.000\n\
+      bye\n";
+
+    assert_eq!(
+      vtt_to_srt(vtt),
+      "1\n00:00:01,000 --> 00:00:04,000\nhello world\n\n\
+       2\n01:00:02,500 --> 01:00:03,000\nbye\n\n"
+    );
+  }
+}
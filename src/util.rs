@@ -1,12 +1,25 @@
-use std::sync::LazyLock;
+use std::{
+  collections::BTreeMap,
+  sync::{
+    atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    LazyLock, Mutex,
+  },
+  time::{Duration, Instant},
+};
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use futures::Future;
+use ring::hmac;
 
 mod byte_stream;
 mod feed_ext;
 
 pub use byte_stream::ByteStream;
-use tokio::sync::Semaphore;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio_graceful_shutdown::SubsystemHandle;
+use tracing::info;
+
+use crate::metrics::METRICS;
 
 #[derive(Default)]
 pub struct W<T>(pub T);
@@ -20,6 +33,148 @@ pub static YTDLP_MUTEX: LazyLock<Semaphore> = LazyLock::new(|| {
   Semaphore::new(concurrency)
 });
 
+// the yt-dlp runs waiting for or holding a slot, by job id
+pub static YTDLP_JOBS: LazyLock<Mutex<BTreeMap<u64, YtdlpJob>>> =
+  LazyLock::new(Default::default);
+
+#[derive(Debug, Clone)]
+pub struct YtdlpJob {
+  pub description: String,
+  pub queued_at: Instant,
+  pub started_at: Option<Instant>,
+}
+
+// a slot in YTDLP_MUTEX, the job is listed until it's dropped
+pub struct YtdlpPermit {
+  job_id: u64,
+  // none while queued
+  slot: Option<SemaphorePermit<'static>>,
+}
+
+impl Drop for YtdlpPermit {
+  fn drop(&mut self) {
+    YTDLP_JOBS.lock().unwrap().remove(&self.job_id);
+    // leaves the queue even if the waiting request is dropped
+    if self.slot.is_none() {
+      METRICS.add("ytdlp_queue_depth", &[], -1.0);
+    }
+  }
+}
+
+// wait for a yt-dlp slot, keeping track of the queue. fails once
+// shutting down.
+pub async fn acquire_ytdlp(
+  description: impl Into<String>,
+) -> crate::Result<YtdlpPermit> {
+  static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(0);
+
+  let job_id = NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed);
+  let queued_at = Instant::now();
+  let job = YtdlpJob {
+    description: description.into(),
+    queued_at,
+    started_at: None,
+  };
+  YTDLP_JOBS.lock().unwrap().insert(job_id, job);
+  METRICS.add("ytdlp_queue_depth", &[], 1.0);
+
+  let mut permit = YtdlpPermit { job_id, slot: None };
+  let slot = YTDLP_MUTEX
+    .acquire()
+    .await
+    .map_err(|_| crate::Error::ShuttingDown)?;
+  permit.slot = Some(slot);
+  METRICS.add("ytdlp_queue_depth", &[], -1.0);
+  METRICS.observe("ytdlp_queue_wait_seconds", &[], queued_at.elapsed());
+
+  if let Some(job) = YTDLP_JOBS.lock().unwrap().get_mut(&job_id) {
+    job.started_at = Some(Instant::now());
+  }
+  Ok(permit)
+}
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+static WORK_IN_PROGRESS: AtomicUsize = AtomicUsize::new(0);
+
+// held by a download or transcode from start until its file is stored
+pub struct WorkGuard(());
+
+impl Drop for WorkGuard {
+  fn drop(&mut self) {
+    WORK_IN_PROGRESS.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+// fails once shutting down, so no new files get started
+pub fn start_work() -> crate::Result<WorkGuard> {
+  // counted before checking, so the drain can't miss it
+  WORK_IN_PROGRESS.fetch_add(1, Ordering::SeqCst);
+  let guard = WorkGuard(());
+  if SHUTTING_DOWN.load(Ordering::SeqCst) {
+    return Err(crate::Error::ShuttingDown);
+  }
+  Ok(guard)
+}
+
+pub fn work_in_progress() -> usize {
+  WORK_IN_PROGRESS.load(Ordering::SeqCst)
+}
+
+fn running_ytdlp_jobs() -> usize {
+  let jobs = YTDLP_JOBS.lock().unwrap();
+  jobs.values().filter(|job| job.started_at.is_some()).count()
+}
+
+// on shutdown, refuse new yt-dlp jobs and downloads, and give the
+// running ones a chance to finish
+pub async fn drain_ytdlp_jobs(subsys: SubsystemHandle) -> crate::Result<()> {
+  subsys.on_shutdown_requested().await;
+
+  SHUTTING_DOWN.store(true, Ordering::SeqCst);
+  // the queued jobs fail instead of starting
+  YTDLP_MUTEX.close();
+
+  let (jobs, work) = (running_ytdlp_jobs(), work_in_progress());
+  if jobs > 0 || work > 0 {
+    info!("waiting for {jobs} yt-dlp jobs and {work} downloads to finish");
+  }
+  while running_ytdlp_jobs() > 0 || work_in_progress() > 0 {
+    tokio::time::sleep(Duration::from_millis(200)).await;
+  }
+  Ok(())
+}
+
+// url-safe HMAC-SHA256 of the message
+pub fn hmac_sign(key: &[u8], message: &str) -> String {
+  let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+  URL_SAFE_NO_PAD.encode(hmac::sign(&key, message.as_bytes()))
+}
+
+// check the signature in constant time
+pub fn hmac_verify(key: &[u8], message: &str, signature: &str) -> bool {
+  let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+    return false;
+  };
+  let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+  hmac::verify(&key, message.as_bytes(), &signature).is_ok()
+}
+
+// don't leak how much of a token was right through the timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len()
+    && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// ensure only a limited set of ffmpeg processes at a time
+pub static FFMPEG_MUTEX: LazyLock<Semaphore> = LazyLock::new(|| {
+  let concurrency = std::env::var("FFMPEG_CONCURRENCY")
+    .ok()
+    .and_then(|s| s.parse::<usize>().ok())
+    .unwrap_or(1);
+  Semaphore::new(concurrency)
+});
+
 // Races multiple futures concurrently and returns the first future that resolves to an `Ok` result,
 // while preserving the order of the input futures.
 //
@@ -61,6 +216,20 @@ pub async fn race_ordered_first_ok<A, E>(
   Err(last_err.unwrap())
 }
 
+// give up on the future after the timeout, if any
+pub async fn with_timeout<A>(
+  timeout: Option<Duration>,
+  name: &'static str,
+  fut: impl Future<Output = crate::Result<A>>,
+) -> crate::Result<A> {
+  match timeout {
+    Some(timeout) => tokio::time::timeout(timeout, fut)
+      .await
+      .unwrap_or(Err(crate::Error::BackendTimeout(name))),
+    None => fut.await,
+  }
+}
+
 #[cfg(test)]
 mod test {
   use std::time::Duration;
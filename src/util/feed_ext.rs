@@ -1,4 +1,4 @@
-use crate::{piped::PipedInstance, INSTANCE_PUBLIC_URL};
+use crate::piped::PipedInstance;
 use atom_syndication::{extension::Extension, Entry};
 use http_types::Url;
 use serde_query::{DeserializeQuery, Query};
@@ -97,9 +97,8 @@ impl W<&Entry> {
   }
 
   pub fn audio_info(&self) -> Result<AudioInfo> {
-    let url = translate_video_to_audio_url(&self.link()?)?;
-    let mime_type = "audio/mpeg".to_string();
-    Ok(AudioInfo { url, mime_type })
+    let video_id = video_id_from_url(&self.link()?)?;
+    Ok(AudioInfo::for_video(&video_id))
   }
 
   pub async fn piped_audio_info(
@@ -146,7 +145,7 @@ impl W<&Entry> {
   }
 }
 
-fn translate_video_to_audio_url(uri_str: &str) -> Result<String> {
+fn video_id_from_url(uri_str: &str) -> Result<String> {
   let uri: Url = uri_str.parse()?;
   let video_id = uri
     .query_pairs()
@@ -155,5 +154,5 @@ fn translate_video_to_audio_url(uri_str: &str) -> Result<String> {
       Error::UnsupportedURL(uri_str.into(), "v parameter not found")
     })?;
 
-  Ok(format!("{}/audio/{video_id}", &*INSTANCE_PUBLIC_URL))
+  Ok(video_id.into_owned())
 }
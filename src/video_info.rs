@@ -0,0 +1,203 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, LazyLock, Mutex},
+  time::Duration,
+};
+
+use futures::Future;
+use lru_time_cache::LruCache;
+use serde::Deserialize;
+use tokio::{process::Command, sync::Semaphore};
+
+use crate::{
+  extractor,
+  util::{acquire_ytdlp, YTDLP_PROXY},
+  Error, Result,
+};
+
+// the parts of yt-dlp's info json of a single video we are interested in
+#[derive(Debug, Deserialize)]
+pub struct VideoInfo {
+  pub channel_id: Option<String>,
+  // the channel handle, e.g. "@name"
+  pub uploader_id: Option<String>,
+  pub description: Option<String>,
+  pub chapters: Option<Vec<InfoChapter>>,
+  // uploaded captions by language
+  #[serde(default)]
+  pub subtitles: HashMap<String, Vec<CaptionFormat>>,
+  #[serde(default)]
+  pub automatic_captions: HashMap<String, Vec<CaptionFormat>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CaptionFormat {
+  pub ext: Option<String>,
+  pub url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InfoChapter {
+  pub start_time: f64,
+  pub title: String,
+}
+
+impl VideoInfo {
+  // the url of the webvtt captions in the language, preferring uploaded
+  // captions over auto-generated ones and the exact language over
+  // regional variants (e.g. "en-US" for "en").
+  pub fn caption_url(&self, lang: &str) -> Option<&str> {
+    let region_prefix = format!("{lang}-");
+    [&self.subtitles, &self.automatic_captions]
+      .into_iter()
+      .flat_map(|tracks| {
+        let exact = tracks.get(lang);
+        let regional = tracks
+          .iter()
+          .filter(|(k, _)| k.starts_with(&region_prefix))
+          .map(|(_, v)| v);
+        exact.into_iter().chain(regional)
+      })
+      .flatten()
+      .find(|f| f.ext.as_deref() == Some("vtt"))
+      .and_then(|f| f.url.as_deref())
+  }
+}
+
+// podcast apps tend to fetch the metadata of an episode repeatedly
+static VIDEO_INFO_CACHE: LazyLock<Mutex<LruCache<String, Arc<VideoInfo>>>> =
+  LazyLock::new(|| {
+    Mutex::new(LruCache::with_expiry_duration_and_capacity(
+      Duration::from_secs(60 * 60),
+      100,
+    ))
+  });
+
+// a lock per video being fetched, so concurrent misses wait for the
+// first one's yt-dlp run instead of starting their own
+type Fetching = HashMap<String, Arc<tokio::sync::Mutex<()>>>;
+
+static FETCHING: LazyLock<Mutex<Fetching>> = LazyLock::new(Default::default);
+
+// the info lookups share the yt-dlp slots with the downloads. only this
+// many of them wait for or hold a slot at a time, so the chapters and
+// transcripts of a whole feed don't queue up ahead of the downloads.
+static VIDEO_INFO_CONCURRENCY: LazyLock<Semaphore> = LazyLock::new(|| {
+  let concurrency = std::env::var("VIDEO_INFO_CONCURRENCY")
+    .ok()
+    .and_then(|s| s.parse::<usize>().ok())
+    .unwrap_or(1);
+  Semaphore::new(concurrency)
+});
+
+// whether fetching the info would be free
+pub fn is_cached(video_id: &str) -> bool {
+  VIDEO_INFO_CACHE.lock().unwrap().peek(video_id).is_some()
+}
+
+// run yt-dlp command line to get the info json of the video.
+// requires yt-dlp executable to be in PATH.
+pub async fn fetch(video_id: &str) -> Result<Arc<VideoInfo>> {
+  if let Some(info) = VIDEO_INFO_CACHE.lock().unwrap().get(video_id) {
+    return Ok(info.clone());
+  }
+
+  fetch_once(video_id, || run_ytdlp(video_id)).await
+}
+
+async fn fetch_once<F, Fut>(video_id: &str, fetch: F) -> Result<Arc<VideoInfo>>
+where
+  F: FnOnce() -> Fut,
+  Fut: Future<Output = Result<VideoInfo>>,
+{
+  let lock = FETCHING
+    .lock()
+    .unwrap()
+    .entry(video_id.to_string())
+    .or_default()
+    .clone();
+
+  let guard = lock.lock().await;
+  // fetched while we were waiting
+  let cached = VIDEO_INFO_CACHE.lock().unwrap().peek(video_id).cloned();
+  let result = match cached {
+    Some(info) => Ok(info),
+    None => fetch().await.map(Arc::new).inspect(|info| {
+      VIDEO_INFO_CACHE
+        .lock()
+        .unwrap()
+        .insert(video_id.to_string(), info.clone());
+    }),
+  };
+  drop(guard);
+
+  // the last one out cleans up
+  let mut fetching = FETCHING.lock().unwrap();
+  if Arc::strong_count(&lock) == 2 {
+    fetching.remove(video_id);
+  }
+  result
+}
+
+async fn run_ytdlp(video_id: &str) -> Result<VideoInfo> {
+  let url = format!("https://youtube.com/watch?v={video_id}");
+  let mut cmd = Command::new("yt-dlp");
+  cmd
+    .arg("-j")
+    .arg("--skip-download")
+    .arg(url)
+    // a dropped request doesn't leave yt-dlp running
+    .kill_on_drop(true);
+  if let Some(proxy) = &*YTDLP_PROXY {
+    cmd.arg("--proxy").arg(proxy);
+  }
+
+  let _lookup = VIDEO_INFO_CONCURRENCY
+    .acquire()
+    .await
+    .map_err(|_| Error::ShuttingDown)?;
+  let guard = acquire_ytdlp(format!("video info {video_id}")).await?;
+  let output = cmd.output().await?;
+  drop(guard);
+
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // e.g. a private or removed video
+    if let Some(e) = extractor::classify(&stderr) {
+      return Err(e.into());
+    }
+    let message = format!(
+      "yt-dlp exited with code ({:?}): {}",
+      output.status.code(),
+      stderr
+    );
+    return Err(Error::VideoInfo(message));
+  }
+
+  Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_concurrent_misses_fetch_once() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FETCHES: AtomicUsize = AtomicUsize::new(0);
+    let video_id = "test_concurrent_misses";
+    let get = || {
+      fetch_once(video_id, || async {
+        FETCHES.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Ok(serde_json::from_str("{}")?)
+      })
+    };
+
+    let (a, b) = tokio::join!(get(), get());
+    assert!(a.is_ok() && b.is_ok());
+    assert_eq!(FETCHES.load(Ordering::SeqCst), 1);
+    assert!(!FETCHING.lock().unwrap().contains_key(video_id));
+  }
+}